@@ -0,0 +1,93 @@
+use crate::kmer::{Base, Kmer};
+use core::hash::Hash;
+use std::collections::{HashMap, HashSet};
+
+/// Reconstructs candidate adapter sequences from over-represented k-mers.
+///
+/// Starting from the most frequent k-mer of `counts` with a count of at least `min_count`,
+/// each candidate is greedily extended on both sides with the most frequent unused
+/// over-represented neighbor. Unless `stranded`, the k-mers of `counts` are canonical,
+/// so a candidate may be reported as the reverse complement of the adapter.
+pub fn infer_adapters<const K: usize, T: Base, KmerT: Kmer<K, T> + Hash>(
+    counts: &HashMap<KmerT, u64>,
+    min_count: u64,
+    stranded: bool,
+) -> Vec<Vec<u8>> {
+    let orient = |kmer: KmerT| if stranded { kmer } else { kmer.canonical() };
+    let mut seeds: Vec<_> = counts
+        .iter()
+        .filter(|&(_, &count)| count >= min_count)
+        .map(|(&kmer, &count)| (count, kmer))
+        .collect();
+    seeds.sort_unstable_by(|a, b| b.cmp(a));
+    let mut used = HashSet::new();
+    let mut adapters = Vec::new();
+    for (_, seed) in seeds {
+        if !used.insert(seed) {
+            continue;
+        }
+        let next = |neighbors: [KmerT; 4], used: &mut HashSet<KmerT>| {
+            let (_, best) = neighbors
+                .iter()
+                .filter_map(|&neighbor| {
+                    let count = *counts.get(&orient(neighbor))?;
+                    (count >= min_count && !used.contains(&orient(neighbor)))
+                        .then_some((count, neighbor))
+                })
+                .max()?;
+            used.insert(orient(best));
+            Some(best)
+        };
+        let mut right = Vec::new();
+        let mut kmer = seed;
+        while let Some(succ) = next(kmer.successors(), &mut used) {
+            right.push(succ.to_bases()[K - 1].to_nuc());
+            kmer = succ;
+        }
+        let mut left = Vec::new();
+        let mut kmer = seed;
+        while let Some(pred) = next(kmer.predecessors(), &mut used) {
+            left.push(pred.to_bases()[0].to_nuc());
+            kmer = pred;
+        }
+        left.reverse();
+        left.extend_from_slice(&seed.to_nucs());
+        left.extend(right);
+        adapters.push(left);
+    }
+    adapters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmer::RawKmer;
+
+    const K: usize = 7;
+    type KmerT = RawKmer<K, u16>;
+
+    #[test]
+    fn test_infer_adapters() {
+        let adapter = b"AGATCGGAAGAGCACACGTCT";
+        let mut counts = HashMap::new();
+        for kmer in KmerT::iter_from_nucs(adapter.iter()) {
+            counts.insert(kmer.canonical(), 1000);
+        }
+        for kmer in KmerT::iter_from_nucs(b"CCATTGACCTTAGGCA".iter()) {
+            counts.entry(kmer.canonical()).or_insert(30);
+        }
+        let adapters = infer_adapters(&counts, 500, false);
+        assert_eq!(adapters.len(), 1);
+        let rev_comp: Vec<u8> = adapter
+            .iter()
+            .rev()
+            .map(|&nuc| match nuc {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect();
+        assert!(adapters[0] == adapter || adapters[0] == rev_comp);
+    }
+}
@@ -0,0 +1,210 @@
+use ahash::RandomState;
+use clap::ValueEnum;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::borrow::Cow;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+
+/// Handling of the ambiguous (non-ACGT) bases of the reads, such as N.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NPolicy {
+    /// Skip the k-mers overlapping them, correcting the parts around them separately and keeping them in place
+    #[default]
+    SkipKmers,
+    /// Replace each of them with a random base, the same in every pass, which can then be corrected
+    ReplaceRandom,
+    /// Split the reads around them, writing each corrected part as a separate read
+    SplitRead,
+    /// Leave uncorrected the reads containing them, whose other k-mers are still counted
+    Keep,
+}
+
+/// Handling of the letters of the reads other than ACGT, when counting and correcting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NucHandling {
+    pub policy: NPolicy,
+    /// Count the IUPAC codes as one of the bases they stand for, instead of as ambiguous bases,
+    /// while leaving them untouched in the corrected reads
+    pub canonicalize_iupac: bool,
+    /// Count the lowercase (soft-masked) bases as their uppercase base, instead of as ambiguous
+    /// bases, while leaving them untouched in the corrected reads
+    pub respect_softmask: bool,
+}
+
+impl NucHandling {
+    pub fn new(policy: NPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the base that `nuc` is counted as, if it is not ACGT but is kept in the output.
+    #[inline]
+    fn counted_as(self, nuc: &u8) -> Option<u8> {
+        let upper = if self.respect_softmask {
+            nuc.to_ascii_uppercase()
+        } else {
+            *nuc
+        };
+        match upper {
+            b'A' | b'C' | b'G' | b'T' if upper != *nuc => Some(upper),
+            _ if self.canonicalize_iupac => iupac_base(&upper),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if `nuc` is counted as another base.
+    #[inline]
+    fn replaces(self, nuc: &u8) -> bool {
+        self.counted_as(nuc).is_some()
+            || (self.policy == NPolicy::ReplaceRandom && is_ambiguous(nuc))
+    }
+
+    /// Returns the sequence whose k-mers are counted: the IUPAC codes are replaced with their first
+    /// base when canonicalized, the soft-masked bases with their uppercase base when respected,
+    /// and the ambiguous bases with random bases for
+    /// [`NPolicy::ReplaceRandom`], or otherwise left in place to skip their k-mers.
+    pub fn resolve(self, nucs: &[u8]) -> Cow<'_, [u8]> {
+        if !nucs.iter().any(|nuc| self.replaces(nuc)) {
+            return Cow::Borrowed(nucs);
+        }
+        // Seeding with the sequence draws the same bases in the counting and the correction.
+        let mut hasher = RandomState::with_seeds(0, 1, 2, 3).build_hasher();
+        nucs.iter()
+            .filter(|nuc| !is_line_break(nuc))
+            .for_each(|&nuc| hasher.write_u8(nuc));
+        let mut rng = SmallRng::seed_from_u64(hasher.finish());
+        Cow::Owned(
+            nucs.iter()
+                .map(|nuc| match self.counted_as(nuc) {
+                    Some(base) => base,
+                    None if self.replaces(nuc) => b"ACGT"[rng.gen_range(0..4)],
+                    None => *nuc,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the letters written for the `resolved` sequence of `nucs`,
+    /// which restore the canonicalized IUPAC codes and the soft-masked bases.
+    pub fn restore<'a>(self, nucs: &[u8], resolved: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.canonicalize_iupac && !self.respect_softmask {
+            return Cow::Borrowed(resolved);
+        }
+        Cow::Owned(
+            nucs.iter()
+                .zip(resolved)
+                .map(|(&nuc, &base)| {
+                    if self.counted_as(&nuc).is_some() {
+                        nuc
+                    } else {
+                        base
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Returns the first base (in ACGT order) of those an IUPAC ambiguity code stands for, except N.
+#[inline]
+pub fn iupac_base(nuc: &u8) -> Option<u8> {
+    match nuc {
+        b'R' | b'W' | b'M' | b'D' | b'H' | b'V' => Some(b'A'),
+        b'Y' | b'S' | b'B' => Some(b'C'),
+        b'K' => Some(b'G'),
+        _ => None,
+    }
+}
+
+/// Returns `true` for the line breaks found in the sequences of multi-line records.
+#[inline]
+pub fn is_line_break(nuc: &u8) -> bool {
+    matches!(nuc, b'\n' | b'\r')
+}
+
+/// Returns `true` for the bases that are neither ACGT nor line breaks.
+#[inline]
+pub fn is_ambiguous(nuc: &u8) -> bool {
+    !matches!(nuc, b'A' | b'C' | b'G' | b'T') && !is_line_break(nuc)
+}
+
+/// Splits `nucs` into its maximal runs of ACGT bases and of ambiguous bases,
+/// along with whether each run is ambiguous.
+pub fn runs(nucs: &[u8]) -> impl Iterator<Item = (Range<usize>, bool)> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        let ambiguous = is_ambiguous(nucs.get(start)?);
+        let len = nucs[start..]
+            .iter()
+            .position(|nuc| is_ambiguous(nuc) != ambiguous)
+            .unwrap_or(nucs.len() - start);
+        start += len;
+        Some((start - len..start, ambiguous))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        let runs: Vec<_> = runs(b"NACGNNT").collect();
+        assert_eq!(
+            runs,
+            vec![(0..1, true), (1..4, false), (4..6, true), (6..7, false)]
+        );
+        assert_eq!(super::runs(b"").count(), 0);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let nucs = b"ACGNNTN\nA";
+        assert_eq!(NucHandling::default().resolve(nucs), &nucs[..]);
+        let handling = NucHandling::new(NPolicy::ReplaceRandom);
+        let resolved = handling.resolve(nucs);
+        assert_eq!(resolved, handling.resolve(nucs));
+        let joined: Vec<u8> = resolved
+            .iter()
+            .copied()
+            .filter(|nuc| !is_line_break(nuc))
+            .collect();
+        assert_eq!(joined, handling.resolve(b"ACGNNTNA").to_vec());
+        assert!(!resolved.iter().any(is_ambiguous));
+        assert_eq!(resolved[..3], nucs[..3]);
+        assert_eq!(resolved[7], b'\n');
+    }
+
+    #[test]
+    fn test_iupac() {
+        let nucs = b"ACRTNK";
+        assert_eq!(NucHandling::default().resolve(nucs), &nucs[..]);
+        let handling = NucHandling {
+            canonicalize_iupac: true,
+            ..NucHandling::default()
+        };
+        let resolved = handling.resolve(nucs);
+        assert_eq!(resolved, &b"ACATNG"[..]);
+        assert_eq!(handling.restore(nucs, &resolved), &nucs[..]);
+    }
+
+    #[test]
+    fn test_softmask() {
+        let nucs = b"ACgtNnr";
+        let handling = NucHandling {
+            respect_softmask: true,
+            ..NucHandling::default()
+        };
+        let resolved = handling.resolve(nucs);
+        assert_eq!(resolved, &b"ACGTNnr"[..]);
+        assert_eq!(handling.restore(nucs, &resolved), &nucs[..]);
+        let handling = NucHandling {
+            canonicalize_iupac: true,
+            ..handling
+        };
+        assert_eq!(handling.resolve(nucs), &b"ACGTNnA"[..]);
+    }
+}
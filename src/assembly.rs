@@ -0,0 +1,131 @@
+use crate::correction::run_kmers;
+use crate::kmer::{Base, Kmer, RawKmer};
+use crate::{for_each_kmer_with_minimizer, M, MT};
+use ahash::RandomState;
+use std::collections::HashMap;
+
+/// Maximum number of reads gathered around a weak region, so that repeats do not make
+/// each local assembly as costly as a global one.
+const MAX_LOCAL_READS: usize = 256;
+
+/// Reads buffered in memory and indexed by the minimizers of their k-mers, from which local
+/// de Bruijn graphs are built around the weak regions that no solid path corrects.
+pub struct LocalAssembler {
+    seed: u64,
+    stranded: bool,
+    nucs: Vec<u8>,
+    ends: Vec<usize>,
+    index: HashMap<RawKmer<M, MT>, Vec<u32>, RandomState>,
+}
+
+impl LocalAssembler {
+    pub fn new(seed: u64, stranded: bool) -> Self {
+        Self {
+            seed,
+            stranded,
+            nucs: Vec::new(),
+            ends: Vec::new(),
+            index: HashMap::with_hasher(RandomState::with_seeds(seed, seed, seed, seed)),
+        }
+    }
+
+    /// Number of reads buffered.
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Buffers the resolved read `nucs` and indexes it by the distinct `minimizers` of its k-mers
+    /// (see [`read_minimizers`]).
+    pub fn add(&mut self, nucs: &[u8], minimizers: &[RawKmer<M, MT>]) {
+        let id = self.ends.len() as u32;
+        self.nucs.extend_from_slice(nucs);
+        self.ends.push(self.nucs.len());
+        for &minimizer in minimizers {
+            self.index.entry(minimizer).or_default().push(id);
+        }
+    }
+
+    fn read(&self, id: u32) -> &[u8] {
+        let start = id.checked_sub(1).map_or(0, |prev| self.ends[prev as usize]);
+        &self.nucs[start..self.ends[id as usize]]
+    }
+
+    /// Counts the canonical k-mers of the reads sharing the minimizer of `source` or of `target`.
+    pub fn local_counts<const K: usize, T: Base, KmerT: Kmer<K, T>>(
+        &self,
+        source: KmerT,
+        target: KmerT,
+    ) -> HashMap<KmerT, usize, RandomState> {
+        let mut minimizers = Vec::new();
+        let mut ids: Vec<u32> = [source, target]
+            .iter()
+            .flat_map(|kmer| {
+                let nucs: Vec<u8> = kmer.to_bases().iter().map(|base| base.to_nuc()).collect();
+                read_minimizers(&nucs, self.seed, self.stranded, &mut minimizers);
+                minimizers
+                    .iter()
+                    .flat_map(|minimizer| self.index.get(minimizer))
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let mut counts = HashMap::with_hasher(self.index.hasher().clone());
+        for &id in ids.iter().take(MAX_LOCAL_READS) {
+            for kmer in run_kmers::<K, T, KmerT>(self.read(id)) {
+                *counts.entry(kmer.canonical()).or_default() += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Writes into `minimizers` the distinct minimizers of the k-mers of the resolved read `nucs`,
+/// skipping the k-mers overlapping an ambiguous base.
+pub fn read_minimizers(
+    nucs: &[u8],
+    seed: u64,
+    stranded: bool,
+    minimizers: &mut Vec<RawKmer<M, MT>>,
+) {
+    minimizers.clear();
+    for_each_kmer_with_minimizer(nucs.iter(), seed, stranded, |_, min| minimizers.push(min));
+    minimizers.sort_unstable();
+    minimizers.dedup();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{random_nucs, rev_comp};
+    use crate::{K, KT};
+
+    type KmerT = RawKmer<K, KT>;
+
+    #[test]
+    fn test_local_counts() {
+        let genome = random_nucs(100, 42);
+        let mut assembler = LocalAssembler::new(42, false);
+        let mut minimizers = Vec::new();
+        let mut add = |nucs: &[u8]| {
+            read_minimizers(nucs, 42, false, &mut minimizers);
+            assembler.add(nucs, &minimizers);
+        };
+        add(&genome[..60]);
+        add(&rev_comp(&genome[..60]));
+        add(&[b'T'; 60]);
+        // The k-mers overlapping the N are not counted.
+        let mut masked = genome[..60].to_vec();
+        masked[50] = b'N';
+        add(&masked);
+        assert_eq!(assembler.len(), 4);
+        let kmers: Vec<KmerT> = KmerT::iter_from_nucs(genome.iter()).collect();
+        // Both strands are gathered around the k-mers they share, but not the unrelated read.
+        let counts = assembler.local_counts(kmers[10], kmers[12]);
+        assert_eq!(counts[&kmers[11].canonical()], 3);
+        assert_eq!(counts[&kmers[25].canonical()], 2);
+        assert!(!counts.contains_key(&KmerT::from_bases(&[3; K]).canonical()));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::reads::open_file;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Result};
+use std::path::Path;
+
+const BAM_MAGIC: &[u8; 4] = b"BAM\x01";
+const BAM_BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+const FLAG_READ1: u16 = 0x40;
+const FLAG_READ2: u16 = 0x80;
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+/// Quality given to the bases of records without qualities, as `samtools fastq` does.
+const DEFAULT_QUALITY: u8 = b'!' + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlignmentFormat {
+    Bam,
+    Sam,
+}
+
+/// Returns the format of `path` if it is an alignment file,
+/// recognized by the magic number of BAM or by the `.sam` extension or header of SAM.
+pub fn alignment_format(path: &str) -> Option<AlignmentFormat> {
+    let mut start = [0; 4];
+    let len = open_file(path).read(&mut start).unwrap_or(0);
+    let name = path
+        .trim_end_matches(".gz")
+        .trim_end_matches(".bz2")
+        .trim_end_matches(".xz")
+        .trim_end_matches(".zst");
+    if &start[..len] == BAM_MAGIC {
+        Some(AlignmentFormat::Bam)
+    } else if name.ends_with(".sam") || [b"@HD\t", b"@SQ\t"].contains(&&start) {
+        Some(AlignmentFormat::Sam)
+    } else {
+        None
+    }
+}
+
+/// Returns the name of the FASTQ file into which the reads of an alignment file are written.
+pub fn fastq_filename(path: &str) -> String {
+    let name = path
+        .trim_end_matches(".gz")
+        .trim_end_matches(".bz2")
+        .trim_end_matches(".xz")
+        .trim_end_matches(".zst");
+    Path::new(name)
+        .with_extension("fq")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn complement(nuc: u8) -> u8 {
+    match nuc {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn skip<R: Read>(reader: &mut R, len: i32) -> Result<()> {
+    let len = len.max(0) as u64;
+    let skipped = std::io::copy(&mut reader.take(len), &mut std::io::sink())?;
+    if skipped < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Stream converting the records of an alignment file into FASTQ as it is read,
+/// so that the reads go through the same parsing as FASTQ files.
+///
+/// Secondary and supplementary alignments are skipped, reads aligned on the reverse strand are
+/// restored to their sequenced orientation, and mates are suffixed with `/1` and `/2`.
+pub struct AlignmentToFastq<R> {
+    reader: BufReader<R>,
+    format: AlignmentFormat,
+    header_read: bool,
+    line: Vec<u8>,
+    record: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> AlignmentToFastq<R> {
+    pub fn new(reader: R, format: AlignmentFormat) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            format,
+            header_read: false,
+            line: Vec::new(),
+            record: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Writes a record into the FASTQ buffer, unless it is a secondary or supplementary alignment.
+    fn push_record(&mut self, name: &[u8], flag: u16, seq: &[u8], qual: Option<&[u8]>) {
+        if flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) != 0 {
+            return;
+        }
+        let record = &mut self.record;
+        record.push(b'@');
+        record.extend_from_slice(name);
+        if flag & FLAG_READ1 != 0 {
+            record.extend_from_slice(b"/1");
+        } else if flag & FLAG_READ2 != 0 {
+            record.extend_from_slice(b"/2");
+        }
+        record.push(b'\n');
+        let start = record.len();
+        record.extend_from_slice(seq);
+        if flag & FLAG_REVERSE != 0 {
+            record[start..].reverse();
+            record[start..]
+                .iter_mut()
+                .for_each(|nuc| *nuc = complement(*nuc));
+        }
+        record.extend_from_slice(b"\n+\n");
+        let start = record.len();
+        match qual {
+            Some(qual) => record.extend_from_slice(qual),
+            None => record.resize(start + seq.len(), DEFAULT_QUALITY),
+        }
+        if flag & FLAG_REVERSE != 0 {
+            record[start..].reverse();
+        }
+        record.push(b'\n');
+    }
+
+    /// Converts the next BAM record, returning `false` at the end of the file.
+    fn next_bam_record(&mut self) -> Result<bool> {
+        if !self.header_read {
+            let mut magic = [0; 4];
+            self.reader.read_exact(&mut magic)?;
+            if &magic != BAM_MAGIC {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid BAM file",
+                ));
+            }
+            let l_text = read_i32(&mut self.reader)?;
+            skip(&mut self.reader, l_text)?;
+            for _ in 0..read_i32(&mut self.reader)? {
+                let l_name = read_i32(&mut self.reader)?;
+                skip(&mut self.reader, l_name + 4)?;
+            }
+            self.header_read = true;
+        }
+        let block_size = match read_i32(&mut self.reader) {
+            Ok(block_size) => block_size,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut block = std::mem::take(&mut self.line);
+        block.resize(block_size.max(0) as usize, 0);
+        self.reader.read_exact(&mut block)?;
+        let invalid = || std::io::Error::new(ErrorKind::InvalidData, "Invalid BAM record");
+        if block.len() < 32 {
+            return Err(invalid());
+        }
+        let l_read_name = block[8] as usize;
+        let n_cigar_op = u16::from_le_bytes([block[12], block[13]]) as usize;
+        let flag = u16::from_le_bytes([block[14], block[15]]);
+        let l_seq = u32::from_le_bytes(block[16..20].try_into().unwrap()) as usize;
+        let seq_start = 32 + l_read_name + 4 * n_cigar_op;
+        let qual_start = seq_start + l_seq.div_ceil(2);
+        if block.len() < qual_start + l_seq || l_read_name == 0 {
+            return Err(invalid());
+        }
+        let name = &block[32..32 + l_read_name - 1];
+        let seq: Vec<u8> = (0..l_seq)
+            .map(|i| {
+                let byte = block[seq_start + i / 2];
+                let code = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+                BAM_BASES[code as usize]
+            })
+            .collect();
+        let qual = &block[qual_start..qual_start + l_seq];
+        let qual: Option<Vec<u8>> = (l_seq > 0 && qual[0] != 0xFF)
+            .then(|| qual.iter().map(|q| q.saturating_add(b'!')).collect());
+        self.push_record(name, flag, &seq, qual.as_deref());
+        self.line = block;
+        Ok(true)
+    }
+
+    /// Converts the next SAM record, returning `false` at the end of the file.
+    fn next_sam_record(&mut self) -> Result<bool> {
+        let mut line = std::mem::take(&mut self.line);
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                self.line = line;
+                return Ok(false);
+            }
+            if !line.starts_with(b"@") && !line.trim_ascii().is_empty() {
+                break;
+            }
+        }
+        let fields: Vec<&[u8]> = line.trim_ascii_end().split(|&c| c == b'\t').collect();
+        if fields.len() < 11 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "Invalid SAM record",
+            ));
+        }
+        let flag = std::str::from_utf8(fields[1])
+            .ok()
+            .and_then(|flag| flag.parse().ok())
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Invalid SAM flag"))?;
+        let (seq, qual) = (fields[9], fields[10]);
+        if seq != b"*" {
+            let seq = seq.to_ascii_uppercase();
+            let qual = (qual != b"*").then_some(qual);
+            self.push_record(fields[0], flag, &seq, qual);
+        }
+        self.line = line;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for AlignmentToFastq<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.pos == self.record.len() {
+            self.record.clear();
+            self.pos = 0;
+            let more = match self.format {
+                AlignmentFormat::Bam => self.next_bam_record()?,
+                AlignmentFormat::Sam => self.next_sam_record()?,
+            };
+            if !more {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.record.len() - self.pos);
+        buf[..n].copy_from_slice(&self.record[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Opens several alignment files as a single FASTQ stream.
+pub fn open_alignment_files<P: AsRef<str>>(paths: &[P]) -> Box<dyn Read + Send> {
+    paths
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let format = alignment_format(path).expect("Not an alignment file");
+            Box::new(AlignmentToFastq::new(open_file(path), format)) as Box<dyn Read + Send>
+        })
+        .reduce(|a, b| Box::new(a.chain(b)))
+        .unwrap_or_else(|| Box::new(std::io::empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_fastq(data: Vec<u8>, format: AlignmentFormat) -> String {
+        let mut fastq = String::new();
+        AlignmentToFastq::new(std::io::Cursor::new(data), format)
+            .read_to_string(&mut fastq)
+            .unwrap();
+        fastq
+    }
+
+    #[test]
+    fn test_sam() {
+        let sam = "@HD\tVN:1.6\tSO:unsorted\n\
+                   r0\t77\t*\t0\t0\t*\t*\t0\t0\tACGTN\tIIIII\n\
+                   r0\t141\t*\t0\t0\t*\t*\t0\t0\tAACG\t*\n\
+                   r1\t16\tchr1\t1\t60\t3M\t*\t0\t0\tAAC\tABC\n\
+                   r1\t256\tchr1\t9\t60\t3M\t*\t0\t0\tAAC\tABC\n";
+        assert_eq!(
+            to_fastq(sam.as_bytes().to_vec(), AlignmentFormat::Sam),
+            "@r0/1\nACGTN\n+\nIIIII\n@r0/2\nAACG\n+\n\"\"\"\"\n@r1\nGTT\n+\nCBA\n"
+        );
+    }
+
+    #[test]
+    fn test_bam() {
+        let mut bam = BAM_MAGIC.to_vec();
+        bam.extend(4i32.to_le_bytes());
+        bam.extend(b"@CO\n");
+        bam.extend(1i32.to_le_bytes());
+        bam.extend(5i32.to_le_bytes());
+        bam.extend(b"chr1\0");
+        bam.extend(1000i32.to_le_bytes());
+        let mut record = Vec::new();
+        record.extend((-1i32).to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.extend([3, 0]);
+        record.extend(4680u16.to_le_bytes());
+        record.extend(0u16.to_le_bytes());
+        record.extend((FLAG_READ1 | FLAG_REVERSE).to_le_bytes());
+        record.extend(3u32.to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.extend(0i32.to_le_bytes());
+        record.extend(b"r0\0");
+        record.extend([0x12, 0x40]);
+        record.extend([30, 31, 32]);
+        bam.extend((record.len() as i32).to_le_bytes());
+        bam.extend(record);
+        assert_eq!(to_fastq(bam, AlignmentFormat::Bam), "@r0/1\nCGT\n+\nA@?\n");
+    }
+}
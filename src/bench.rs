@@ -0,0 +1,236 @@
+use crate::ambiguous::NucHandling;
+use crate::constants::{K, KT, M};
+use crate::correction::{correct, Stats};
+use crate::dashbloom::CountingBloomFilter;
+use crate::kmer::{Kmer, RawKmer};
+use crate::reads::{Fasta, ReadProcess};
+use crate::{count_kmers, thread_count};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::io::{Read, Result};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Amount of reads (in bases) generated on the fly, with an optional K, M, G or T suffix
+    #[arg(long, value_parser = parse_size)]
+    synthetic: usize,
+    /// Coverage of the synthetic genome by the reads
+    #[arg(short, long, default_value_t = 40)]
+    coverage: usize,
+    /// Probability that each base of a read is substituted
+    #[arg(short, long, default_value_t = 0.005)]
+    error_rate: f64,
+    /// Length of the synthetic reads
+    #[arg(short = 'l', long, default_value_t = 150)]
+    read_length: usize,
+    /// Numbers of threads to benchmark, separated by commas (defaults to powers of two up to all available threads)
+    #[arg(short, long, value_delimiter = ',')]
+    threads: Vec<usize>,
+    /// Memory (in MB) allocated to Bloom filters (defaults to the size of the synthetic reads)
+    #[arg(short, long)]
+    memory: Option<usize>,
+    /// Abundance above which k-mers are solid
+    #[arg(short, long, default_value_t = 5)]
+    abundance: u8,
+    /// Number of hashes used in Bloom filters
+    #[arg(short = 'H', long, default_value_t = 3)]
+    hashes: usize,
+    /// Seed used for hash functions and for the synthetic data
+    #[arg(short, long, default_value_t = 101010)]
+    seed: u64,
+}
+
+/// Parses a number of bases such as `10G`, with decimal suffixes.
+fn parse_size(s: &str) -> std::result::Result<usize, String> {
+    let (digits, factor) = match s.as_bytes().last().map(u8::to_ascii_uppercase) {
+        Some(b'K') => (&s[..s.len() - 1], 1e3),
+        Some(b'M') => (&s[..s.len() - 1], 1e6),
+        Some(b'G') => (&s[..s.len() - 1], 1e9),
+        Some(b'T') => (&s[..s.len() - 1], 1e12),
+        _ => (s, 1.0),
+    };
+    digits
+        .parse::<f64>()
+        .map(|x| (x * factor) as usize)
+        .map_err(|e| e.to_string())
+}
+
+/// FASTA stream of reads sampled from `genome` on both strands, with uniform substitutions,
+/// generated as it is read so that the synthetic data never touches the disk.
+struct SyntheticReads {
+    genome: Arc<Vec<u8>>,
+    read_length: usize,
+    error_rate: f64,
+    remaining: usize,
+    rng: SmallRng,
+    id: usize,
+    record: Vec<u8>,
+    pos: usize,
+}
+
+impl SyntheticReads {
+    fn new(
+        genome: Arc<Vec<u8>>,
+        read_length: usize,
+        error_rate: f64,
+        bases: usize,
+        seed: u64,
+    ) -> Self {
+        Self {
+            genome,
+            read_length,
+            error_rate,
+            remaining: bases / read_length,
+            rng: SmallRng::seed_from_u64(seed),
+            id: 0,
+            record: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn next_record(&mut self) {
+        self.record.clear();
+        self.pos = 0;
+        self.record.extend(format!(">r{}\n", self.id).bytes());
+        self.id += 1;
+        self.remaining -= 1;
+        let start = self.rng.gen_range(0..=self.genome.len() - self.read_length);
+        let forward = self.rng.gen_bool(0.5);
+        for i in 0..self.read_length {
+            let mut base = if forward {
+                self.genome[start + i]
+            } else {
+                3 - self.genome[start + self.read_length - 1 - i]
+            };
+            if self.rng.gen_bool(self.error_rate) {
+                base = (base + self.rng.gen_range(1..4)) % 4;
+            }
+            self.record.push(b"ACGT"[base as usize]);
+        }
+        self.record.push(b'\n');
+    }
+}
+
+impl Read for SyntheticReads {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos == self.record.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.next_record();
+        }
+        let n = buf.len().min(self.record.len() - self.pos);
+        buf[..n].copy_from_slice(&self.record[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Runs the counting and correction passes on synthetic reads generated on the fly,
+/// and reports their throughput for each number of threads.
+pub fn bench(args: BenchArgs) {
+    assert!(
+        args.coverage >= 1 && args.read_length >= K,
+        "The coverage must be ≥ 1 and the reads must be at least K bases long"
+    );
+    let genome_size = (args.synthetic / args.coverage).max(args.read_length);
+    let mut rng = SmallRng::seed_from_u64(args.seed);
+    let genome: Arc<Vec<u8>> = Arc::new((0..genome_size).map(|_| rng.gen_range(0..4)).collect());
+    let thread_counts = if args.threads.is_empty() {
+        let max = thread_count(None);
+        let mut counts: Vec<_> = (0..).map(|i| 1 << i).take_while(|&t| t < max).collect();
+        counts.push(max);
+        counts
+    } else {
+        args.threads.clone()
+    };
+    let size = args
+        .memory
+        .map_or(args.synthetic / 2, |m| m * 1_000_000 / 2);
+    let min_threshold = args.abundance.div_ceil(2);
+    let kmer_threshold = args.abundance + 1 - min_threshold;
+    let open_reads = || {
+        let reads = SyntheticReads::new(
+            genome.clone(),
+            args.read_length,
+            args.error_rate,
+            args.synthetic,
+            args.seed + 1,
+        );
+        Fasta::from_reader_with_batch_size(reads, crate::reads::DEFAULT_BATCH_SIZE)
+    };
+
+    println!("threads\tcounting (Mbp/s)\tcorrection (Mbp/s)");
+    for threads in thread_counts {
+        let shard_amount = threads * 4;
+        let new_filter = |seed| {
+            CountingBloomFilter::new_with_seed_and_shard_amount(
+                size,
+                args.hashes,
+                seed,
+                shard_amount,
+            )
+        };
+        let min_counts = new_filter(args.seed + M as u64);
+        let kmer_counts = new_filter(args.seed + K as u64);
+        let start = Instant::now();
+        count_kmers(
+            open_reads(),
+            threads,
+            &min_counts,
+            &kmer_counts,
+            min_threshold,
+            args.seed,
+            false,
+            NucHandling::default(),
+        );
+        let counting = start.elapsed();
+
+        let start = Instant::now();
+        open_reads().process_par_result(
+            threads as u32,
+            32,
+            |nucs, (buffer, stats): &mut (Vec<u8>, Stats)| {
+                correct(
+                    nucs,
+                    None,
+                    NucHandling::default(),
+                    |kmer: RawKmer<K, KT>| kmer_counts.count(kmer.canonical()) >= kmer_threshold,
+                    |kmer: RawKmer<K, KT>| kmer_counts.prefetch(kmer.canonical()),
+                    |_, _| false,
+                    |_| true,
+                    None,
+                    None,
+                    None,
+                    buffer,
+                    &mut Vec::new(),
+                    &mut Vec::new(),
+                    stats,
+                );
+            },
+            |_| (),
+        );
+        let correction = start.elapsed();
+        let mbp = args.synthetic as f64 / 1e6;
+        println!(
+            "{threads}\t{:.2}\t{:.2}",
+            mbp / counting.as_secs_f64(),
+            mbp / correction.as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("10G"), Ok(10_000_000_000));
+        assert_eq!(parse_size("1.5k"), Ok(1_500));
+        assert_eq!(parse_size("42"), Ok(42));
+        assert!(parse_size("G").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::ambiguous::NucHandling;
+use crate::bam::{alignment_format, fastq_filename};
+use crate::constants::{K, KT, M};
+use crate::correction::run_kmers;
+use crate::dashbloom::BloomFilter;
+use crate::kmer::{Kmer, RawKmer};
+use crate::output::{FinishWrite, Outputs};
+use crate::reads::{
+    content_size, is_fastq, Bam, BaseRecord, Fasta, Fastq, ReadProcess, Truncations,
+};
+use crate::{
+    dominant_minimizer, tagged_filename, tagged_output_filename, thread_count, write_read,
+};
+use ahash::RandomState;
+use clap::ValueHint;
+use std::io::BufWriter;
+use std::path::Path;
+use std::slice::Iter;
+
+const W: usize = K - M + 1;
+
+#[derive(clap::Args, Debug)]
+pub struct BinArgs {
+    /// Input file (.fasta, .fa, .fastq, .fq, .bam, .sam, optionally compressed)
+    #[arg(value_hint = ValueHint::FilePath)]
+    input: String,
+    /// Output file, bins are written to <output>.<bin>.<ext> (defaults to <input>.bin.<ext>)
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Number of threads (defaults to all available threads)
+    #[arg(short, long)]
+    threads: Option<usize>,
+    /// Number of bins used when binning by dominant minimizer
+    #[arg(short, long, default_value_t = 16)]
+    bins: usize,
+    /// Reference file (.fasta, .fa, .fastq, .fq, .bam, .sam, optionally compressed)
+    /// whose k-mers define a bin (can be repeated)
+    #[arg(short, long = "ref", value_hint = ValueHint::FilePath)]
+    refs: Vec<String>,
+    /// Minimum fraction of k-mers shared with a reference to assign a read to its bin
+    #[arg(short = 'f', long, default_value_t = 0.5)]
+    min_fraction: f64,
+    /// Number of hashes used in Bloom filters
+    #[arg(short = 'H', long, default_value_t = 3)]
+    hashes: usize,
+    /// Seed used for hash functions
+    #[arg(short, long, default_value_t = 101010)]
+    seed: u64,
+    /// Overwrite the existing outputs instead of refusing to start
+    #[arg(long)]
+    force: bool,
+}
+
+pub fn bin(args: BinArgs, truncations: &Truncations) {
+    let input = args.input.clone();
+    if alignment_format(&input).is_some() {
+        bin_with(args, Bam::from_file(&input).truncations(truncations))
+    } else if is_fastq(&input) {
+        bin_with(args, Fastq::from_file(&input).truncations(truncations))
+    } else {
+        bin_with(args, Fasta::from_file(&input))
+    }
+}
+
+/// Assigns the `reads` of the input to bins, writing each of them in the format of its record.
+fn bin_with<R: ReadProcess>(args: BinArgs, reads: R) {
+    assert!(
+        (0.0..=1.0).contains(&args.min_fraction),
+        "The minimum fraction must be between 0 and 1"
+    );
+    let threads = thread_count(args.threads);
+    let output_filename = args.output.unwrap_or_else(|| {
+        let name = tagged_filename(&args.input, "bin");
+        // Alignment inputs are written as FASTQ.
+        if alignment_format(&args.input).is_some() {
+            fastq_filename(&name)
+        } else {
+            name
+        }
+    });
+    let mut names: Vec<String> = if args.refs.is_empty() {
+        assert!(args.bins >= 1, "The number of bins must be ≥ 1");
+        (0..args.bins).map(|i| i.to_string()).collect()
+    } else {
+        args.refs
+            .iter()
+            .map(|filename| ref_name(filename))
+            .collect()
+    };
+    names.push("unassigned".to_owned());
+    let filenames: Vec<_> = names
+        .iter()
+        .map(|name| tagged_output_filename(&output_filename, name))
+        .collect();
+    let mut outputs = Outputs::new(true).threads(threads).overwrite(args.force);
+    filenames
+        .iter()
+        .try_for_each(|filename| outputs.check(filename))
+        .expect("Failed to open output file");
+    let mut writers: Vec<_> = filenames
+        .iter()
+        .map(|filename| {
+            BufWriter::new(
+                outputs
+                    .create(filename)
+                    .expect("Failed to open output file"),
+            )
+        })
+        .collect();
+    let unassigned = names.len() - 1;
+
+    let write_record = |record: R::Rec<'_>, bin: &mut usize| {
+        let qual = record.opt_full_qual().unwrap_or_default();
+        write_read(&mut writers[*bin], &record, &record.full_seq(), &qual)
+            .expect("Failed to write record");
+    };
+    let handling = NucHandling::default();
+    if args.refs.is_empty() {
+        let hasher =
+            RandomState::with_seeds(args.seed, args.seed + 1, args.seed + 2, args.seed + 3);
+        reads.process_rec_par_result(
+            threads as u32,
+            32,
+            |record, bin: &mut usize| {
+                let nucs = handling.resolve(record.seq());
+                *bin = dominant_minimizer(&nucs, args.seed + W as u64, false)
+                    .map_or(unassigned, |(min, _, _)| {
+                        hasher.hash_one(min) as usize % args.bins
+                    });
+            },
+            write_record,
+        );
+    } else {
+        let filters: Vec<_> = args
+            .refs
+            .iter()
+            .map(|filename| build_filter(filename, args.hashes, args.seed, threads, handling))
+            .collect();
+        reads.process_rec_par_result(
+            threads as u32,
+            32,
+            |record, bin: &mut usize| {
+                let mut hits = vec![0; filters.len()];
+                let mut total = 0;
+                for kmer in run_kmers::<K, KT, RawKmer<K, KT>>(&handling.resolve(record.seq())) {
+                    let kmer = kmer.canonical();
+                    total += 1;
+                    for (hit, filter) in hits.iter_mut().zip(filters.iter()) {
+                        if filter.contains(kmer) {
+                            *hit += 1;
+                        }
+                    }
+                }
+                *bin = unassigned;
+                if let Some((best, &count)) = hits.iter().enumerate().max_by_key(|(_, &c)| c) {
+                    if total > 0 && count as f64 >= args.min_fraction * total as f64 {
+                        *bin = best;
+                    }
+                }
+            },
+            write_record,
+        );
+    }
+    for writer in writers.iter_mut() {
+        writer.finish().expect("Failed to finish output");
+    }
+    drop(writers);
+    outputs.commit().expect("Failed to rename output files");
+}
+
+/// Names a reference bin after its file name, without extensions.
+fn ref_name(filename: &str) -> String {
+    let name = Path::new(filename)
+        .file_name()
+        .map_or(filename.into(), |name| name.to_string_lossy());
+    name.split('.').next().unwrap_or_default().to_owned()
+}
+
+/// Builds a membership filter of the canonical k-mers of a reference,
+/// using about 10 bits per base, its letters other than ACGT being handled according to `handling`.
+pub fn build_filter(
+    filename: &str,
+    n_hashes: usize,
+    seed: u64,
+    threads: usize,
+    handling: NucHandling,
+) -> BloomFilter {
+    let size = content_size(filename) * 10;
+    let filter =
+        BloomFilter::new_with_seed_and_shard_amount(size, n_hashes, seed + K as u64, threads * 4);
+    let insert = |nucs: Iter<u8>| {
+        run_kmers(&handling.resolve(nucs.as_slice()))
+            .for_each(|kmer: RawKmer<K, KT>| filter.insert(kmer.canonical()));
+    };
+    if alignment_format(filename).is_some() {
+        Bam::from_file(filename).process_par(threads as u32, 32, insert);
+    } else if is_fastq(filename) {
+        Fastq::from_file(filename).process_par(threads as u32, 32, insert);
+    } else {
+        Fasta::from_file(filename).process_par(threads as u32, 32, insert);
+    }
+    filter
+}
@@ -76,6 +76,25 @@ impl BloomFilter {
         }
         missing
     }
+
+    /// Divides the size of the filter by `factor` by OR-ing its `factor` parts together,
+    /// keeping every inserted item at the cost of a higher false positive rate.
+    /// The folded size must remain a multiple of the block size.
+    pub fn fold(&mut self, factor: usize) {
+        assert!(
+            factor >= 1 && self.size.is_multiple_of(factor * Self::BLOCK_SIZE),
+            "The folded filter must be a multiple of the block size"
+        );
+        let size = self.size / factor;
+        self.bv = BitVec::from_fn(size, |i| (0..factor).any(|k| self.bv[i + k * size]));
+        self.size = size;
+    }
+
+    /// False positive rate of the filter, estimated from the fraction of bits set.
+    pub fn fpr(&self) -> f64 {
+        let load = self.bv.iter().filter(|&bit| bit).count() as f64 / self.size as f64;
+        load.powi(self.n_hashes as i32)
+    }
 }
 
 pub struct CascadingBloomFilter {
@@ -208,6 +227,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fold() {
+        let mut bf = BloomFilter::new(1 << 20, 3);
+        for x in 0..1000 {
+            bf.insert(x);
+        }
+        let fpr = bf.fpr();
+        bf.fold(16);
+        for x in 0..1000 {
+            assert!(bf.contains(x));
+        }
+        assert!(bf.fpr() > fpr);
+    }
+
     #[test]
     fn test_cascading() {
         let sizes = &[1 << 20, 1 << 19, 1 << 18];
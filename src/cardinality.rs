@@ -0,0 +1,81 @@
+use ahash::RandomState;
+use core::hash::Hash;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Number of bits of the hashes selecting a register, for a relative error of about 1%.
+const PRECISION: u32 = 14;
+
+/// HyperLogLog sketch estimating the number of distinct items inserted, shared between threads.
+pub struct HyperLogLog {
+    registers: Vec<AtomicU8>,
+    hash_builder: RandomState,
+}
+
+impl HyperLogLog {
+    pub fn new_with_seed(seed: u64) -> Self {
+        Self {
+            registers: (0..1 << PRECISION).map(|_| AtomicU8::new(0)).collect(),
+            hash_builder: RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3),
+        }
+    }
+
+    pub fn insert<T: Hash>(&self, item: T) {
+        let hash = self.hash_builder.hash_one(item);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The sentinel bit bounds the rank when the remaining bits are all zeros.
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[index].fetch_max(rank, Ordering::Relaxed);
+    }
+
+    /// Estimated number of distinct items, using linear counting for small cardinalities.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for register in self.registers.iter() {
+            let rank = register.load(Ordering::Relaxed);
+            sum += 2f64.powi(-(rank as i32));
+            zeros += (rank == 0) as usize;
+        }
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// Number of hashes minimizing the false positive rate of a filter of `counters` counters
+/// holding `items` distinct items, i.e. `counters / items * ln 2`, between 1 and `max_hashes`.
+pub fn optimal_hashes(counters: usize, items: f64, max_hashes: usize) -> usize {
+    let hashes = (counters as f64 / items.max(1.0) * std::f64::consts::LN_2).round();
+    (hashes as usize).clamp(1, max_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        for n in [100, 10_000, 1_000_000] {
+            let hll = HyperLogLog::new_with_seed(42);
+            for i in 0..n {
+                hll.insert(i);
+                hll.insert(i);
+            }
+            let error = (hll.estimate() - n as f64).abs() / n as f64;
+            assert!(error < 0.05, "{n} items estimated as {}", hll.estimate());
+        }
+    }
+
+    #[test]
+    fn test_optimal_hashes() {
+        assert_eq!(optimal_hashes(1000, 100.0, 8), 7);
+        assert_eq!(optimal_hashes(1000, 250.0, 8), 3);
+        assert_eq!(optimal_hashes(1000, 10.0, 8), 8);
+        assert_eq!(optimal_hashes(1000, 1e6, 8), 1);
+        assert_eq!(optimal_hashes(1000, 0.0, 8), 8);
+    }
+}
@@ -1,73 +1,453 @@
+use crate::ambiguous::{is_ambiguous, is_line_break, runs, NPolicy, NucHandling};
+use crate::assembly::LocalAssembler;
 use crate::kmer::{Base, Kmer};
 use crate::mutation::Mutation;
+use crate::stats::Merge;
+use crate::substitution::SubstitutionProfile;
 use core::cmp::min;
 use derive_more::AddAssign;
 use std::collections::VecDeque;
+use std::ops::Range;
 use std::slice::Iter;
+use std::time::Instant;
 
 #[derive(Debug, Clone, Copy, Default, AddAssign)]
 pub struct Stats {
+    /// Weak regions for which a correction was attempted
     pub errors: usize,
+    /// Weak regions that were corrected
     pub corrections: usize,
+    /// Corrections made by a single substitution, insertion or deletion, without searching a path
+    pub single_edits: usize,
+    /// Corrections found in the local assembly of the reads around weak regions without solid path
+    pub local_corrections: usize,
+    /// Attempts that failed because no solid path joins the flanking k-mers
+    pub no_path: usize,
+    /// Attempts that failed because several solid paths join the flanking k-mers
+    pub multiple_paths: usize,
+    /// Corrections chosen among several solid paths using the substitution profile
+    pub resolved_ties: usize,
+    /// Weak regions that were too short or too long to be corrected
     pub skipped_errors: usize,
+    /// Skipped weak regions that were too long to be corrected
+    pub long_gaps: usize,
+    /// Weak regions left untouched because their position is protected
+    pub protected_errors: usize,
+    /// Weak regions left untouched because a flanking k-mer cannot serve as an anchor
+    pub unanchored_errors: usize,
+    /// Reads that were not corrected because they looked solid enough
+    pub prescreened_reads: usize,
+    /// Reads that were not corrected because their mean base quality is high enough
+    pub high_quality_reads: usize,
+    /// Reads left uncorrected because they are dominated by a single minimizer (tandem repeats)
+    pub repeat_reads: usize,
+    /// Reads left uncorrected because their k-mers mostly belong to the host reference
+    pub host_reads: usize,
+    /// Reads left uncorrected because the corrections would change too many of their bases
+    pub guarded_reads: usize,
+    /// Reads left uncorrected because their time budget was exhausted
+    pub timeouts: usize,
+    /// Reads left uncorrected because their correction failed
+    pub failed_reads: usize,
 }
 
-pub fn correct<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
+impl Merge for Stats {
+    fn merge(&mut self, other: &Self) {
+        *self += *other;
+    }
+}
+
+/// Reason why no correcting path was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathError {
+    NoPath,
+    Timeout,
+}
+
+/// Iterates over the k-mers of the runs of ACGT bases of a resolved sequence (see
+/// [`NucHandling::resolve`]), skipping those overlapping an ambiguous base instead of joining
+/// the bases around it.
+pub fn run_kmers<'a, const K: usize, T: Base + 'a, KmerT: Kmer<K, T> + 'a>(
+    nucs: &'a [u8],
+) -> impl Iterator<Item = KmerT> + 'a {
+    runs(nucs)
+        .filter(|(_, ambiguous)| !ambiguous)
+        .flat_map(move |(run, _)| KmerT::iter_from_nucs(nucs[run].iter()))
+}
+
+/// Estimates the fraction of weak k-mers of a resolved read by only querying one k-mer
+/// every `stride` positions.
+pub fn sampled_weak_fraction<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
+    nucs: &[u8],
+    solid: F,
+    stride: usize,
+) -> f64 {
+    let (weak, total) = run_kmers(nucs)
+        .step_by(stride)
+        .fold((0, 0), |(weak, total), kmer| {
+            (weak + !solid(kmer) as usize, total + 1)
+        });
+    if total == 0 {
+        0.0
+    } else {
+        weak as f64 / total as f64
+    }
+}
+
+/// Number of k-mers ahead of the one queried whose counters are prefetched.
+const PREFETCH_DISTANCE: usize = 8;
+
+/// Iterates over the solidity of `kmers`, calling `prefetch` on the k-mer `PREFETCH_DISTANCE`
+/// positions ahead of each query, so that the loads of the filter overlap instead of stalling
+/// the scan one k-mer at a time.
+pub fn solidities<'a, KmerT: Copy, F: Fn(KmerT) -> bool + 'a, P: Fn(KmerT) + 'a>(
+    kmers: &'a [KmerT],
+    solid: F,
+    prefetch: P,
+) -> impl Iterator<Item = bool> + 'a {
+    kmers
+        .iter()
+        .take(PREFETCH_DISTANCE)
+        .for_each(|&kmer| prefetch(kmer));
+    kmers.iter().enumerate().map(move |(i, &kmer)| {
+        if let Some(&ahead) = kmers.get(i + PREFETCH_DISTANCE) {
+            prefetch(ahead);
+        }
+        solid(kmer)
+    })
+}
+
+/// Corrects the weak regions of a read into `buffer`.
+///
+/// The letters other than ACGT are handled according to `handling`: the parts of the read around
+/// the ambiguous bases are corrected separately, and their ends are written into `parts` when the
+/// read is split ([`NPolicy::SplitRead`]).
+///
+/// When the `quals` of the read are given, the corrected qualities are written into `qual_buffer`:
+/// substituted bases keep their original quality, inserted bases get the lowest quality of their
+/// neighbours and deleted bases drop theirs, as do the ambiguous bases removed from the read.
+///
+/// The `prefetch` hint is given the k-mers whose solidity is about to be queried (see [`solidities`]).
+/// A weak region is left untouched when `protected` holds for its two flanking solid k-mers,
+/// or when one of them does not satisfy `anchor`.
+/// If the `deadline` is reached, the read is left uncorrected.
+/// When several solid paths join the flanking k-mers, the `profile` of the sequencer
+/// can choose the substitutions that most likely produced the read.
+/// When none does, the path is searched in the local assembly of the reads gathered by the `assembler`.
+#[allow(clippy::too_many_arguments)]
+pub fn correct<
+    const K: usize,
+    T: Base,
+    KmerT: Kmer<K, T>,
+    F: Fn(KmerT) -> bool,
+    P: Fn(KmerT),
+    G: Fn(KmerT, KmerT) -> bool,
+    H: Fn(KmerT) -> bool,
+>(
     nucs: Iter<'_, u8>,
+    quals: Option<&[u8]>,
+    handling: NucHandling,
     solid: F,
+    prefetch: P,
+    protected: G,
+    anchor: H,
+    deadline: Option<Instant>,
+    profile: Option<&SubstitutionProfile>,
+    assembler: Option<&LocalAssembler>,
     buffer: &mut Vec<u8>,
+    qual_buffer: &mut Vec<u8>,
+    parts: &mut Vec<usize>,
     stats: &mut Stats,
 ) {
     buffer.clear();
+    qual_buffer.clear();
+    parts.clear();
     *stats = Stats::default();
-    let mut kmer = KmerT::new();
+    let mut kept_quals = Vec::new();
+    let nucs: Vec<u8> = match quals {
+        Some(quals) => nucs
+            .zip(quals)
+            .filter(|(nuc, _)| !is_line_break(nuc))
+            .map(|(&nuc, &qual)| {
+                kept_quals.push(qual);
+                nuc
+            })
+            .collect(),
+        None => nucs.copied().filter(|nuc| !is_line_break(nuc)).collect(),
+    };
+    let resolved = handling.resolve(&nucs);
+    let letters = handling.restore(&nucs, &resolved);
+    let split = handling.policy == NPolicy::SplitRead;
+    let uncorrected = |buffer: &mut Vec<u8>, qual_buffer: &mut Vec<u8>, parts: &mut Vec<usize>| {
+        for (run, ambiguous) in runs(&resolved) {
+            if !(split && ambiguous) {
+                buffer.extend_from_slice(&letters[run.clone()]);
+                qual_buffer.extend(kept_quals.get(run).unwrap_or_default());
+            }
+            if split && !ambiguous {
+                parts.push(buffer.len());
+            }
+        }
+    };
+    if handling.policy == NPolicy::Keep && resolved.iter().any(is_ambiguous) {
+        uncorrected(buffer, qual_buffer, parts);
+        return;
+    }
+    for (run, ambiguous) in runs(&resolved) {
+        let run_quals = kept_quals.get(run.clone()).unwrap_or_default();
+        if ambiguous {
+            if !split {
+                buffer.extend_from_slice(&letters[run]);
+                qual_buffer.extend_from_slice(run_quals);
+            }
+            continue;
+        }
+        let bases: Vec<T> = resolved[run.clone()]
+            .iter()
+            .filter_map(T::from_nuc)
+            .collect();
+        let completed = correct_bases(
+            &bases,
+            &letters[run],
+            run_quals,
+            &solid,
+            &prefetch,
+            &protected,
+            &anchor,
+            deadline,
+            profile,
+            assembler,
+            buffer,
+            qual_buffer,
+            stats,
+        );
+        if !completed {
+            buffer.clear();
+            qual_buffer.clear();
+            parts.clear();
+            uncorrected(buffer, qual_buffer, parts);
+            *stats = Stats {
+                timeouts: 1,
+                ..Stats::default()
+            };
+            return;
+        }
+        if split {
+            parts.push(buffer.len());
+        }
+    }
+}
+
+/// Corrects the weak regions of a run of ACGT bases, appending them to `buffer` along with their
+/// `quals` (if any) to `qual_buffer`, and adding to `stats`.
+/// The bases are written as their `letters`, and the weak regions containing a letter that differs
+/// from its base (e.g. an IUPAC code counted as one of its bases) are left untouched.
+/// Returns `false` if the `deadline` was reached, leaving the buffers to be overwritten.
+#[allow(clippy::too_many_arguments)]
+fn correct_bases<
+    const K: usize,
+    T: Base,
+    KmerT: Kmer<K, T>,
+    F: Fn(KmerT) -> bool,
+    P: Fn(KmerT),
+    G: Fn(KmerT, KmerT) -> bool,
+    H: Fn(KmerT) -> bool,
+>(
+    bases: &[T],
+    letters: &[u8],
+    quals: &[u8],
+    solid: F,
+    prefetch: P,
+    protected: G,
+    anchor: H,
+    deadline: Option<Instant>,
+    profile: Option<&SubstitutionProfile>,
+    assembler: Option<&LocalAssembler>,
+    buffer: &mut Vec<u8>,
+    qual_buffer: &mut Vec<u8>,
+    stats: &mut Stats,
+) -> bool {
+    let kmers: Vec<KmerT> = KmerT::iter_from_bases(bases.iter().copied()).collect();
+    let fixed = |range: Range<usize>| range.into_iter().any(|i| letters[i] != bases[i].to_nuc());
+    buffer.extend(letters.iter().take(K - 1));
+    qual_buffer.extend(quals.iter().take(K - 1));
     let mut last_solid_kmer = KmerT::new();
     let mut weak_bases = Vec::new();
+    let mut weak_start = 0;
     let mut error_size = 0;
-    for (i, base) in nucs.filter_map(T::from_nuc).enumerate() {
-        if i < K - 1 {
-            kmer = kmer.extend(base);
-            buffer.push(base.to_nuc());
-        } else {
-            kmer = kmer.append(base);
-            match (solid(kmer), error_size) {
-                (true, 0) => {
-                    buffer.push(base.to_nuc());
-                    last_solid_kmer = kmer;
-                }
-                (false, 0) => {
-                    error_size = 1;
-                    weak_bases = kmer.to_bases().to_vec();
-                }
-                (false, _) => {
-                    error_size += 1;
-                    weak_bases.push(base);
-                }
-                (true, _) => {
-                    if K / 2 < error_size && error_size < 2 * K {
-                        stats.errors += 1;
-                        if let Some((middle, d0, d1)) =
-                            find_path(last_solid_kmer, kmer, 2, error_size + 1, &solid)
-                        {
-                            weak_bases = last_solid_kmer.to_bases()[1..d0].to_vec();
-                            weak_bases.extend_from_slice(&middle.to_bases());
-                            weak_bases.extend_from_slice(&kmer.to_bases()[(K - d1)..(K - 1)]);
-                            stats.corrections += 1;
-                        }
+    for (i, ((&kmer, is_solid), &base)) in kmers
+        .iter()
+        .zip(solidities(&kmers, &solid, &prefetch))
+        .zip(bases.iter().skip(K - 1))
+        .enumerate()
+    {
+        let pos = i + K - 1;
+        match (is_solid, error_size) {
+            (true, 0) => {
+                buffer.push(letters[pos]);
+                qual_buffer.extend(quals.get(pos));
+                last_solid_kmer = kmer;
+            }
+            (false, 0) => {
+                error_size = 1;
+                weak_bases = kmer.to_bases().to_vec();
+                weak_start = pos;
+            }
+            (false, _) => {
+                error_size += 1;
+                weak_bases.push(base);
+            }
+            (true, _) => {
+                let mut corrected = false;
+                if fixed(weak_start..pos) || protected(last_solid_kmer, kmer) {
+                    stats.protected_errors += 1;
+                } else if !anchor(last_solid_kmer) || !anchor(kmer) {
+                    stats.unanchored_errors += 1;
+                } else if K / 2 < error_size && error_size < 2 * K {
+                    stats.errors += 1;
+                    let edited = (error_size <= K + 1)
+                        .then(|| single_edit(&weak_bases, base, &solid))
+                        .flatten();
+                    if let Some(bases) = edited {
+                        weak_bases = bases;
+                        corrected = true;
+                        stats.corrections += 1;
+                        stats.single_edits += 1;
                     } else {
-                        stats.skipped_errors += 1;
+                        let mut found =
+                            find_path(last_solid_kmer, kmer, 2, error_size + 1, &solid, deadline);
+                        let mut local = false;
+                        if let (Err(PathError::NoPath), Some(assembler)) = (&found, assembler) {
+                            found = local_path(
+                                assembler,
+                                last_solid_kmer,
+                                kmer,
+                                error_size + 1,
+                                deadline,
+                            );
+                            local = found.is_ok();
+                        }
+                        match found {
+                            Ok((middles, d0, d1)) => {
+                                let candidates: Vec<Vec<T>> = middles
+                                    .iter()
+                                    .map(|middle| {
+                                        let mut bases = last_solid_kmer.to_bases()[1..d0].to_vec();
+                                        bases.extend_from_slice(&middle.to_bases());
+                                        bases
+                                            .extend_from_slice(&kmer.to_bases()[(K - d1)..(K - 1)]);
+                                        bases
+                                    })
+                                    .collect();
+                                let prev = last_solid_kmer.to_bases()[0];
+                                match choose_candidate(candidates, &weak_bases, prev, profile) {
+                                    Some(bases) => {
+                                        if middles.len() > 1 {
+                                            stats.resolved_ties += 1;
+                                        }
+                                        if local {
+                                            stats.local_corrections += 1;
+                                        }
+                                        weak_bases = bases;
+                                        corrected = true;
+                                        stats.corrections += 1;
+                                    }
+                                    None => stats.multiple_paths += 1,
+                                }
+                            }
+                            Err(PathError::NoPath) => stats.no_path += 1,
+                            Err(PathError::Timeout) => return false,
+                        }
                     }
+                } else {
+                    stats.skipped_errors += 1;
+                    if error_size >= 2 * K {
+                        stats.long_gaps += 1;
+                    }
+                }
+                if !quals.is_empty() {
+                    realign_quals(
+                        &bases[weak_start..pos],
+                        &weak_bases[(K - 1)..],
+                        &quals[weak_start..pos],
+                        qual_buffer,
+                    );
+                }
+                if corrected {
                     buffer.extend(weak_bases.drain((K - 1)..).map(|base| base.to_nuc()));
-                    error_size = 0;
-                    buffer.push(base.to_nuc());
-                    last_solid_kmer = kmer;
+                } else {
+                    buffer.extend_from_slice(&letters[weak_start..pos]);
                 }
+                error_size = 0;
+                buffer.push(letters[pos]);
+                qual_buffer.extend(quals.get(pos));
+                last_solid_kmer = kmer;
             }
         }
     }
     if error_size > 0 {
-        buffer.extend(weak_bases.drain((K - 1)..).map(|base| base.to_nuc()));
+        qual_buffer.extend(quals.iter().skip(weak_start));
+        buffer.extend_from_slice(&letters[weak_start..]);
+    }
+    true
+}
+
+/// Returns the lengths of the common prefix and of the common suffix of `a` and `b`, not overlapping.
+pub fn common_ends<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (prefix, suffix)
+}
+
+/// Counts the bases of `original` changed into `corrected`: the substituted bases when both have
+/// the same length, or else the longest of the regions between their common prefix and suffix.
+pub fn changed_bases(original: &[u8], corrected: &[u8]) -> usize {
+    if original.len() == corrected.len() {
+        return original
+            .iter()
+            .zip(corrected)
+            .filter(|(a, b)| a != b)
+            .count();
+    }
+    let (prefix, suffix) = common_ends(original, corrected);
+    (original.len() - prefix - suffix).max(corrected.len() - prefix - suffix)
+}
+
+/// Bases of the original read `seq` to compare with its corrected bases, without the line breaks
+/// and, if the reads are split around them with `n_policy`, without the ambiguous bases.
+pub fn comparable_bases(seq: &[u8], n_policy: NPolicy) -> Vec<u8> {
+    let split = n_policy == NPolicy::SplitRead;
+    seq.iter()
+        .filter(|nuc| !(is_line_break(nuc) || (split && is_ambiguous(nuc))))
+        .copied()
+        .collect()
+}
+
+/// Writes into `buffer` the qualities of the `corrected` bases replacing the `original` ones:
+/// bases paired with an original base keep its quality, while the bases inserted after the
+/// common prefix get the lowest quality around them and the deleted ones drop theirs.
+fn realign_quals<T: Base>(original: &[T], corrected: &[T], quals: &[u8], buffer: &mut Vec<u8>) {
+    if original.len() == corrected.len() {
+        buffer.extend_from_slice(quals);
+        return;
+    }
+    let (prefix, suffix) = common_ends(original, corrected);
+    let replaced = original.len() - prefix - suffix;
+    let replacing = corrected.len() - prefix - suffix;
+    let kept = replaced.min(replacing);
+    buffer.extend_from_slice(&quals[..(prefix + kept)]);
+    if replacing > kept {
+        let around =
+            &quals[(prefix + kept).saturating_sub(1)..(prefix + kept + 1).min(quals.len())];
+        let synthesized = around.iter().copied().min().unwrap_or(b'!');
+        buffer.extend(std::iter::repeat_n(synthesized, replacing - kept));
     }
+    buffer.extend_from_slice(&quals[(quals.len() - suffix)..]);
 }
 
 fn validate<
@@ -83,13 +463,65 @@ fn validate<
     KmerT::iter_from_bases(bases).all(solid)
 }
 
+/// Minimum number of occurrences of a k-mer in the reads around a weak region for it to be part
+/// of their local assembly, the k-mers of the read being corrected occurring once.
+const LOCAL_ABUNDANCE: usize = 2;
+/// Number of slots of the cache of solidity used by each path search.
+const SOLIDITY_CACHE_SLOTS: usize = 1 << 10;
+/// Number of slots probed before giving up on caching a k-mer.
+const SOLIDITY_CACHE_PROBES: usize = 8;
+
+/// Small open-addressing cache of the solidity of k-mers, keyed by their value, since the
+/// successors and predecessors explored by a path search overlap and are queried repeatedly.
+struct SolidityCache<KmerT> {
+    slots: Vec<Option<(KmerT, bool)>>,
+}
+
+impl<KmerT: Copy + Eq> SolidityCache<KmerT> {
+    fn new() -> Self {
+        Self {
+            slots: vec![None; SOLIDITY_CACHE_SLOTS],
+        }
+    }
+
+    /// Returns the solidity of `kmer`, only querying `solid` if it is not cached.
+    /// K-mers whose probed slots are all taken are queried without being cached.
+    fn solid<const K: usize, T: Base, F: Fn(KmerT) -> bool>(
+        &mut self,
+        kmer: KmerT,
+        solid: F,
+    ) -> bool
+    where
+        KmerT: Kmer<K, T>,
+    {
+        let int = kmer.to_int().to_u128().unwrap();
+        let hash = (int as u64 ^ (int >> 64) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let start = (hash >> (64 - SOLIDITY_CACHE_SLOTS.trailing_zeros())) as usize;
+        for probe in 0..SOLIDITY_CACHE_PROBES {
+            let slot = &mut self.slots[(start + probe) % SOLIDITY_CACHE_SLOTS];
+            match *slot {
+                Some((cached, is_solid)) if cached == kmer => return is_solid,
+                Some(_) => continue,
+                None => {
+                    let is_solid = solid(kmer);
+                    *slot = Some((kmer, is_solid));
+                    return is_solid;
+                }
+            }
+        }
+        solid(kmer)
+    }
+}
+
 fn find_path<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
     source: KmerT,
     target: KmerT,
     min_dist: usize,
     max_dist: usize,
     solid: F,
-) -> Option<(KmerT, usize, usize)> {
+    deadline: Option<Instant>,
+) -> Result<(Vec<KmerT>, usize, usize), PathError> {
+    let mut cache = SolidityCache::new();
     let mut forward = VecDeque::new();
     forward.push_back(source);
     let mut backward = VecDeque::new();
@@ -97,10 +529,17 @@ fn find_path<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
     let mut middle = Vec::new();
     let mut dist = (0, 0);
     for i in 0..max_dist.div_ceil(2) {
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(PathError::Timeout);
+        }
         let n = forward.len();
         for _ in 0..n {
             let kmer = forward.pop_front().unwrap();
-            forward.extend(kmer.successors().iter().filter(|&&succ| solid(succ)));
+            for succ in kmer.successors() {
+                if cache.solid(succ, &solid) {
+                    forward.push_back(succ);
+                }
+            }
         }
         if 2 * i + 1 >= min_dist {
             middle = forward
@@ -115,7 +554,11 @@ fn find_path<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
         let n: usize = backward.len();
         for _ in 0..n {
             let kmer = backward.pop_front().unwrap();
-            backward.extend(kmer.predecessors().iter().filter(|&&pred| solid(pred)));
+            for pred in kmer.predecessors() {
+                if cache.solid(pred, &solid) {
+                    backward.push_back(pred);
+                }
+            }
         }
         if 2 * i + 2 >= min_dist {
             middle = backward
@@ -128,13 +571,83 @@ fn find_path<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
             }
         }
     }
-    if middle.len() == 1 {
-        Some((middle[0], dist.0, dist.1))
+    if middle.is_empty() {
+        Err(PathError::NoPath)
+    } else {
+        Ok((middle, dist.0, dist.1))
+    }
+}
+
+/// Searches a path joining `source` and `target` through the k-mers seen at least
+/// `LOCAL_ABUNDANCE` times in the reads sharing their minimizers, i.e. in their local assembly.
+fn local_path<const K: usize, T: Base, KmerT: Kmer<K, T>>(
+    assembler: &LocalAssembler,
+    source: KmerT,
+    target: KmerT,
+    max_dist: usize,
+    deadline: Option<Instant>,
+) -> Result<(Vec<KmerT>, usize, usize), PathError> {
+    let counts = assembler.local_counts(source, target);
+    let solid = |kmer: KmerT| {
+        counts
+            .get(&kmer.canonical())
+            .is_some_and(|&count| count >= LOCAL_ABUNDANCE)
+    };
+    find_path(source, target, 2, max_dist, solid, deadline)
+}
+
+/// Chooses the correction among the bases of the solid paths found for a weak region:
+/// the only one if the path is unique, otherwise the one whose substitutions are the most likely
+/// according to the `profile` to have produced the `observed` bases, if it is strictly better.
+fn choose_candidate<T: Base>(
+    mut candidates: Vec<Vec<T>>,
+    observed: &[T],
+    prev: T,
+    profile: Option<&SubstitutionProfile>,
+) -> Option<Vec<T>> {
+    if candidates.len() == 1 {
+        return candidates.pop();
+    }
+    let profile = profile?;
+    if candidates.iter().any(|bases| bases.len() != observed.len()) {
+        return None;
+    }
+    let observed: Vec<_> = observed.iter().map(|base| base.to_nuc()).collect();
+    let mut scores: Vec<_> = candidates
+        .into_iter()
+        .map(|bases| {
+            let nucs: Vec<_> = bases.iter().map(|base| base.to_nuc()).collect();
+            (profile.score(prev.to_nuc(), &nucs, &observed), bases)
+        })
+        .collect();
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+    if scores[0].0 > scores[1].0 {
+        Some(scores.swap_remove(0).1)
     } else {
         None
     }
 }
 
+/// Corrects a short weak region by a single edit of its first weak base, if one makes all its
+/// k-mers solid up to the next solid k-mer, which ends with `next` (the base following `weak_bases`).
+/// Substitutions are tried first, then deletions and insertions of a base.
+fn single_edit<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
+    weak_bases: &[T],
+    next: T,
+    solid: F,
+) -> Option<Vec<T>> {
+    let mut bases = weak_bases.to_vec();
+    bases.push(next);
+    let threshold = bases.len();
+    let edited = try_substitution(&mut bases, &solid, threshold)
+        || try_deletion(&mut bases, &solid, threshold)
+        || try_insertion(&mut bases, &solid, threshold);
+    edited.then(|| {
+        bases.pop();
+        bases
+    })
+}
+
 fn try_deletion<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
     weak_bases: &mut Vec<T>,
     solid: F,
@@ -177,7 +690,7 @@ fn try_insertion<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> boo
 }
 
 fn try_substitution<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) -> bool>(
-    weak_bases: &mut Vec<T>,
+    weak_bases: &mut [T],
     solid: F,
     validation_threshold: usize,
 ) -> bool {
@@ -205,3 +718,299 @@ fn try_substitution<const K: usize, T: Base, KmerT: Kmer<K, T>, F: Fn(KmerT) ->
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmer::RawKmer;
+    use clap::ValueEnum;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    const K: usize = 7;
+    type KmerT = RawKmer<K, u16>;
+
+    fn nucs(len: impl Into<proptest::collection::SizeRange>) -> impl Strategy<Value = Vec<u8>> {
+        proptest::collection::vec(prop::sample::select(b"ACGT".to_vec()), len)
+    }
+
+    fn run_correct<F: Fn(KmerT) -> bool>(seq: &[u8], solid: F) -> (Vec<u8>, Stats) {
+        let (buffer, _, stats) = run_correct_with(seq, NucHandling::new(NPolicy::SplitRead), solid);
+        (buffer, stats)
+    }
+
+    fn run_correct_with<F: Fn(KmerT) -> bool>(
+        seq: &[u8],
+        handling: NucHandling,
+        solid: F,
+    ) -> (Vec<u8>, Vec<usize>, Stats) {
+        let mut buffer = Vec::new();
+        let mut parts = Vec::new();
+        let mut stats = Stats::default();
+        correct(
+            seq.iter(),
+            None,
+            handling,
+            solid,
+            |_| (),
+            |_, _| false,
+            |_| true,
+            None,
+            None,
+            None,
+            &mut buffer,
+            &mut Vec::new(),
+            &mut parts,
+            &mut stats,
+        );
+        (buffer, parts, stats)
+    }
+
+    #[test]
+    fn test_choose_candidate() {
+        let bases = |nucs: &[u8]| nucs.iter().filter_map(u16::from_nuc).collect::<Vec<_>>();
+        let observed = bases(b"GATT");
+        let candidates = vec![bases(b"GTTT"), bases(b"GAGT")];
+        let prev = bases(b"A")[0];
+        assert_eq!(
+            choose_candidate(candidates.clone(), &observed, prev, None),
+            None
+        );
+        let profile = SubstitutionProfile::parse("A G T 0.05").unwrap();
+        assert_eq!(
+            choose_candidate(candidates, &observed, prev, Some(&profile)),
+            Some(bases(b"GAGT"))
+        );
+    }
+
+    #[test]
+    fn test_realign_quals() {
+        let bases = |nucs: &[u8]| nucs.iter().filter_map(u16::from_nuc).collect::<Vec<_>>();
+        let realign = |original: &[u8], corrected: &[u8], quals: &[u8]| {
+            let mut buffer = Vec::new();
+            realign_quals(&bases(original), &bases(corrected), quals, &mut buffer);
+            buffer
+        };
+        assert_eq!(realign(b"ACGT", b"AGGT", b"ABCD"), b"ABCD");
+        assert_eq!(realign(b"ACGT", b"ACAGT", b"ABCD"), b"ABBCD");
+        assert_eq!(realign(b"ACGT", b"AGT", b"ABCD"), b"ACD");
+    }
+
+    #[test]
+    fn test_solidity_cache() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let queries = std::cell::RefCell::new(Vec::new());
+        let solid = |kmer| {
+            queries.borrow_mut().push(kmer);
+            solid_kmers.contains(&kmer)
+        };
+        let kmers: Vec<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let (middles, _, _) = find_path(kmers[0], kmers[12], 2, 13, solid, None).unwrap();
+        assert_eq!(middles.len(), 1);
+        let queries = queries.into_inner();
+        let distinct: HashSet<_> = queries.iter().collect();
+        assert_eq!(queries.len(), distinct.len());
+    }
+
+    #[test]
+    fn test_ambiguous_policies() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let solid = |kmer| solid_kmers.contains(&kmer);
+        let mut read = genome.to_vec();
+        read[20] = b'N';
+        let (buffer, parts, _) =
+            run_correct_with(&read, NucHandling::new(NPolicy::SkipKmers), solid);
+        assert_eq!((buffer, parts), (read.clone(), vec![]));
+        let (buffer, parts, _) = run_correct_with(&read, NucHandling::new(NPolicy::Keep), |_| true);
+        assert_eq!(buffer, read);
+        assert!(parts.is_empty());
+        let (buffer, parts, _) =
+            run_correct_with(&read, NucHandling::new(NPolicy::SplitRead), solid);
+        assert_eq!(buffer, [&genome[..20], &genome[21..]].concat());
+        assert_eq!(parts, vec![20, genome.len() - 1]);
+        // The random base is drawn from a seed fixed by the sequence: here a C, which is then
+        // corrected into the G of the genome.
+        let handling = NucHandling::new(NPolicy::ReplaceRandom);
+        assert_eq!(handling.resolve(&read)[20], b'C');
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.corrections), (genome.to_vec(), 1));
+    }
+
+    #[test]
+    fn test_iupac_untouched() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let solid = |kmer| solid_kmers.contains(&kmer);
+        let handling = NucHandling {
+            canonicalize_iupac: true,
+            ..NucHandling::default()
+        };
+        let mut read = genome.to_vec();
+        // K stands for G (or T), so its k-mers are solid.
+        read[20] = b'K';
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.errors), (read.clone(), 0));
+        // R stands for A (or G), so its weak k-mers are left untouched.
+        read[20] = b'R';
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.protected_errors), (read, 1));
+    }
+
+    #[test]
+    fn test_softmask_untouched() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let solid = |kmer| solid_kmers.contains(&kmer);
+        let handling = NucHandling {
+            respect_softmask: true,
+            ..NucHandling::default()
+        };
+        let mut read = genome.to_vec();
+        read[16..24].make_ascii_lowercase();
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.errors), (read.clone(), 0));
+        read[20] = b't';
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.protected_errors), (read.clone(), 1));
+        // The same error is corrected outside of a soft-masked region.
+        read.make_ascii_uppercase();
+        let (buffer, _, stats) = run_correct_with(&read, handling, solid);
+        assert_eq!((buffer, stats.corrections), (genome.to_vec(), 1));
+    }
+
+    #[test]
+    fn test_run_kmers() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let mut read = genome.to_vec();
+        read[20] = b'N';
+        let kmers: Vec<KmerT> = run_kmers(&read).collect();
+        let expected: Vec<KmerT> = KmerT::iter_from_nucs(genome[..20].iter())
+            .chain(KmerT::iter_from_nucs(genome[21..].iter()))
+            .collect();
+        assert_eq!(kmers, expected);
+        // The line breaks of multi-line records do not split the runs.
+        let wrapped = [&genome[..20], b"\n", &genome[20..]].concat();
+        let kmers: Vec<KmerT> = run_kmers(&wrapped).collect();
+        assert_eq!(
+            kmers,
+            KmerT::iter_from_nucs(genome.iter()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_solidities() {
+        let kmers: Vec<usize> = (0..20).collect();
+        let prefetched = std::cell::RefCell::new(Vec::new());
+        let solid: Vec<bool> = solidities(
+            &kmers,
+            |kmer| kmer % 3 == 0,
+            |kmer| prefetched.borrow_mut().push(kmer),
+        )
+        .collect();
+        assert_eq!(
+            solid,
+            kmers.iter().map(|kmer| kmer % 3 == 0).collect::<Vec<_>>()
+        );
+        // Every k-mer is prefetched once, before being queried.
+        assert_eq!(prefetched.into_inner(), kmers);
+    }
+
+    #[test]
+    fn test_single_edits() {
+        let genome = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+        let solid = |kmer| solid_kmers.contains(&kmer);
+        let substituted = [&genome[..20], b"T", &genome[21..]].concat();
+        let inserted = [&genome[..20], b"T", &genome[20..]].concat();
+        let deleted = [&genome[..20], &genome[21..]].concat();
+        for read in [substituted, inserted, deleted] {
+            let (buffer, stats) = run_correct(&read, solid);
+            assert_eq!((buffer, stats.single_edits), (genome.to_vec(), 1));
+        }
+    }
+
+    #[test]
+    fn test_comparable_bases() {
+        assert_eq!(comparable_bases(b"ACGN\nNTA", NPolicy::SplitRead), b"ACGTA");
+        assert_eq!(
+            comparable_bases(b"ACGN\nNTA", NPolicy::SkipKmers),
+            b"ACGNNTA"
+        );
+    }
+
+    #[test]
+    fn test_changed_bases() {
+        assert_eq!(changed_bases(b"ACGTACGT", b"ACCTACGA"), 2);
+        assert_eq!(changed_bases(b"ACGTACGT", b"ACGTTACGT"), 1);
+        assert_eq!(changed_bases(b"ACGTACGT", b"ACTTCGT"), 3);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_quals_follow_bases(
+            seq in proptest::collection::vec(prop::sample::select(b"ACGTNRa".to_vec()), 0..200),
+            seed: u16,
+            policy in prop::sample::select(NPolicy::value_variants()),
+            canonicalize_iupac: bool,
+            respect_softmask: bool,
+        ) {
+            let solid = |kmer: KmerT| (kmer.to_int() ^ seed).count_ones() % 2 == 0;
+            let quals: Vec<u8> = (0..seq.len()).map(|i| b'!' + (i % 40) as u8).collect();
+            let (mut buffer, mut qual_buffer, mut stats) = (Vec::new(), Vec::new(), Stats::default());
+            correct(
+                seq.iter(),
+                Some(&quals),
+                NucHandling { policy, canonicalize_iupac, respect_softmask },
+                solid,
+                |_| (),
+                |_, _| false,
+                |_| true,
+                None,
+                None,
+                None,
+                &mut buffer,
+                &mut qual_buffer,
+                &mut Vec::new(),
+                &mut stats,
+            );
+            prop_assert_eq!(buffer.len(), qual_buffer.len());
+        }
+
+        #[test]
+        fn prop_valid_bases(
+            seq in proptest::collection::vec(prop::sample::select(b"ACGTN".to_vec()), 0..200),
+            seed: u16,
+        ) {
+            let solid = |kmer: KmerT| (kmer.to_int() ^ seed).count_ones() % 2 == 0;
+            let (buffer, _) = run_correct(&seq, solid);
+            prop_assert!(buffer.iter().all(|b| b"ACGT".contains(b)));
+        }
+
+        #[test]
+        fn prop_all_solid_identity(seq in nucs(0..200)) {
+            let (buffer, stats) = run_correct(&seq, |_| true);
+            prop_assert_eq!(buffer, seq);
+            prop_assert_eq!(stats.errors, 0);
+        }
+
+        #[test]
+        fn prop_corrections_validate(
+            genome in nucs(110..300),
+            start in 0usize..50,
+            len in 40usize..60,
+            pos in 10usize..30,
+            base in prop::sample::select(b"ACGT".to_vec()),
+        ) {
+            let solid_kmers: HashSet<_> = KmerT::iter_from_nucs(genome.iter()).collect();
+            let solid = |kmer| solid_kmers.contains(&kmer);
+            let mut read = genome[start..(start + len)].to_vec();
+            read[pos] = base;
+            let (buffer, stats) = run_correct(&read, solid);
+            if stats.corrections == stats.errors && stats.skipped_errors == 0 {
+                prop_assert!(KmerT::iter_from_nucs(buffer.iter()).all(solid));
+            }
+        }
+    }
+}
@@ -6,6 +6,20 @@ use bit_vec::BitVec;
 use core::hash::Hash;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Hints the CPU to bring the cache line of `ptr` closer, ahead of a query reading it.
+#[inline]
+fn prefetch_read<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: prefetching is a hint that never faults, even on an invalid address.
+    unsafe {
+        core::arch::x86_64::_mm_prefetch(ptr as *const i8, core::arch::x86_64::_MM_HINT_T0);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = ptr;
+}
 
 pub struct BloomFilter {
     shard_shift: usize,
@@ -89,6 +103,16 @@ impl BloomFilter {
         indices.iter().all(|&i| shard.get(i).unwrap_or(false))
     }
 
+    /// Prefetches the block of bits of `x`, all of its hashes falling in the same block,
+    /// so that a query of `x` issued a few items later finds it in cache.
+    pub fn prefetch<T: Hash>(&self, x: T) {
+        let h0 = self.hash_builders.0.hash_one(&x);
+        let shard_idx = (h0 >> (64 - self.shard_shift)) as usize;
+        let u = h0 as usize % self.shard_size;
+        let shard = unsafe { self._yield_read_shard(shard_idx) };
+        prefetch_read(shard.storage().as_ptr().wrapping_add(u / 32));
+    }
+
     pub fn insert<T: Hash>(&self, x: T) {
         let (shard_idx, indices) = self.shard_indices(x);
         let mut shard = unsafe { self._yield_write_shard(shard_idx) };
@@ -107,6 +131,33 @@ impl BloomFilter {
         }
         missing
     }
+
+    /// Divides the size of the filter by `factor` by OR-ing the `factor` parts of each shard,
+    /// keeping every inserted item at the cost of a higher false positive rate.
+    /// The folded shards must remain a multiple of the block size.
+    pub fn fold(&mut self, factor: usize) {
+        assert!(
+            factor >= 1 && self.shard_size.is_multiple_of(factor * Self::BLOCK_SIZE),
+            "The folded shards must be a multiple of the block size"
+        );
+        let size = self.shard_size / factor;
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            *shard = BitVec::from_fn(size, |i| (0..factor).any(|k| shard[i + k * size]));
+        }
+        self.shard_size = size;
+    }
+
+    /// False positive rate of the filter, estimated from the fraction of bits set.
+    pub fn fpr(&self) -> f64 {
+        let ones: usize = self
+            .shards
+            .iter()
+            .map(|shard| shard.read().iter().filter(|&bit| bit).count())
+            .sum();
+        let load = ones as f64 / (self.shard_size * self.shards.len()) as f64;
+        load.powi(self.n_hashes as i32)
+    }
 }
 
 impl<'a> BloomFilter {
@@ -173,12 +224,25 @@ impl CascadingBloomFilter {
     }
 }
 
+/// Estimates of the counts represented by the states of 2-bit Morris counters,
+/// which are incremented from state `v` with probability `2^-v`.
+const MORRIS_ESTIMATES: [u8; 4] = [0, 1, 3, 7];
+
+static NEXT_RNG_SEED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static MORRIS_RNG: RefCell<SmallRng> =
+        RefCell::new(SmallRng::seed_from_u64(NEXT_RNG_SEED.fetch_add(1, Ordering::Relaxed)));
+}
+
 pub struct CountingBloomFilter {
     shard_shift: usize,
     shard_size: usize,
     n_hashes: usize,
+    morris: bool,
     shards: Box<[RwLock<Vec<u8>>]>,
     hash_builders: (RandomState, RandomState),
+    distinct: AtomicUsize,
 }
 
 impl CountingBloomFilter {
@@ -191,23 +255,50 @@ impl CountingBloomFilter {
         n_hashes: usize,
         seed: u64,
         shard_amount: usize,
+    ) -> Self {
+        Self::with_counters(size, n_hashes, seed, shard_amount, false)
+    }
+
+    /// Creates a filter of 2-bit Morris counters, packing 4 times more counters in `size` bytes.
+    ///
+    /// Counters are incremented with a probability halving at each step, so that counts
+    /// are only resolved to 0, 1, 3 or 7 (in expectation) and saturate at 7.
+    /// Increments are conservative: only the counters holding the minimum are incremented.
+    pub fn new_morris_with_seed_and_shard_amount(
+        size: usize,
+        n_hashes: usize,
+        seed: u64,
+        shard_amount: usize,
+    ) -> Self {
+        Self::with_counters(size.saturating_mul(4), n_hashes, seed, shard_amount, true)
+    }
+
+    fn with_counters(
+        counters: usize,
+        n_hashes: usize,
+        seed: u64,
+        shard_amount: usize,
+        morris: bool,
     ) -> Self {
         let shard_amount = shard_amount.next_power_of_two();
         let shard_shift = shard_amount.trailing_zeros() as usize;
-        let shard_size = (size >> shard_shift).saturating_add(Self::BLOCK_SIZE - 1)
+        let shard_size = (counters >> shard_shift).saturating_add(Self::BLOCK_SIZE - 1)
             / Self::BLOCK_SIZE
             * Self::BLOCK_SIZE;
+        let shard_bytes = if morris { shard_size / 4 } else { shard_size };
         Self {
             shard_shift,
             shard_size,
             n_hashes,
+            morris,
             shards: (0..shard_amount)
-                .map(|_| RwLock::new(vec![0; shard_size]))
+                .map(|_| RwLock::new(vec![0; shard_bytes]))
                 .collect(),
             hash_builders: (
                 RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3),
                 RandomState::with_seeds(seed + 4, seed + 5, seed + 6, seed + 7),
             ),
+            distinct: AtomicUsize::new(0),
         }
     }
 
@@ -249,31 +340,96 @@ impl CountingBloomFilter {
         (shard_idx, res)
     }
 
+    fn morris_state(shard: &[u8], i: usize) -> u8 {
+        (shard[i / 4] >> (2 * (i % 4))) & 0b11
+    }
+
     pub fn count<T: Hash>(&self, x: T) -> u8 {
         let (shard_idx, indices) = self.shard_indices(x);
         let shard = unsafe { self._yield_read_shard(shard_idx) };
+        if self.morris {
+            let state = indices
+                .iter()
+                .map(|&i| Self::morris_state(&shard, i))
+                .min()
+                .unwrap_or(0);
+            return MORRIS_ESTIMATES[state as usize];
+        }
         indices.iter().map(|&i| shard[i]).min().unwrap_or(0)
     }
 
+    /// Prefetches the block of counters of `x`, all of its hashes falling in the same block,
+    /// so that a query of `x` issued a few items later finds it in cache.
+    pub fn prefetch<T: Hash>(&self, x: T) {
+        let h0 = self.hash_builders.0.hash_one(&x);
+        let shard_idx = (h0 >> (64 - self.shard_shift)) as usize;
+        let u = h0 as usize % self.shard_size;
+        let shard = unsafe { self._yield_read_shard(shard_idx) };
+        let byte = if self.morris { u / 4 } else { u };
+        prefetch_read(shard.as_ptr().wrapping_add(byte));
+    }
+
     pub fn add<T: Hash>(&self, x: T) {
-        let (shard_idx, indices) = self.shard_indices(x);
-        let mut shard = unsafe { self._yield_write_shard(shard_idx) };
-        indices
-            .iter()
-            .for_each(|&i| shard[i] = shard[i].saturating_add(1));
+        self.add_and_count(x);
     }
 
     pub fn add_and_count<T: Hash>(&self, x: T) -> u8 {
+        if self.morris {
+            return self.morris_add_and_count(x);
+        }
         let (shard_idx, indices) = self.shard_indices(x);
         let mut shard = unsafe { self._yield_write_shard(shard_idx) };
-        indices
+        let count = indices
             .iter()
             .map(|&i| {
                 shard[i] = shard[i].saturating_add(1);
                 shard[i]
             })
             .min()
-            .unwrap_or(0)
+            .unwrap_or(0);
+        if count == 1 {
+            self.distinct.fetch_add(1, Ordering::Relaxed);
+        }
+        count
+    }
+
+    fn morris_add_and_count<T: Hash>(&self, x: T) -> u8 {
+        let (shard_idx, indices) = self.shard_indices(x);
+        let mut shard = unsafe { self._yield_write_shard(shard_idx) };
+        let state = indices
+            .iter()
+            .map(|&i| Self::morris_state(&shard, i))
+            .min()
+            .unwrap_or(0);
+        let increment =
+            state < 3 && MORRIS_RNG.with(|rng| rng.borrow_mut().gen_ratio(1, 1 << state));
+        if !increment {
+            return MORRIS_ESTIMATES[state as usize];
+        }
+        for &i in indices.iter() {
+            if Self::morris_state(&shard, i) == state {
+                shard[i / 4] += 1 << (2 * (i % 4));
+            }
+        }
+        if state == 0 {
+            self.distinct.fetch_add(1, Ordering::Relaxed);
+        }
+        MORRIS_ESTIMATES[state as usize + 1]
+    }
+
+    /// Approximate number of distinct items added so far.
+    ///
+    /// An item is counted on its first touch, i.e. when its count goes from 0 to 1,
+    /// so false positives make this an underestimate.
+    pub fn approx_len(&self) -> usize {
+        self.distinct.load(Ordering::Relaxed)
+    }
+
+    /// Approximate false positive rate of the filter given its number of distinct items.
+    pub fn approx_fpr(&self) -> f64 {
+        let counters = (self.shard_size * self.shards.len()) as f64;
+        let load = (self.n_hashes * self.approx_len()) as f64 / counters;
+        (1.0 - (-load).exp()).powi(self.n_hashes as i32)
     }
 }
 
@@ -311,6 +467,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fold() {
+        let mut bf = BloomFilter::new_with_seed_and_shard_amount(1 << 20, 3, 42, 4);
+        for x in 0..1000 {
+            bf.insert(x);
+        }
+        let fpr = bf.fpr();
+        bf.fold(8);
+        for x in 0..1000 {
+            assert!(bf.contains(x));
+        }
+        assert!(bf.fpr() > fpr);
+        assert!(bf.fpr() < 1e-3);
+    }
+
     #[test]
     fn test_cascading() {
         let sizes = &[1 << 20, 1 << 19, 1 << 18];
@@ -361,6 +532,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_morris() {
+        let size = 1 << 18;
+        let n_hashes = 4;
+        let cbf = CountingBloomFilter::new_morris_with_seed_and_shard_amount(size, n_hashes, 42, 4);
+        for x in 0..1000 {
+            cbf.add(x);
+        }
+        for x in 0..1000 {
+            assert_eq!(cbf.count(x), 1);
+        }
+        for x in 1000..2000 {
+            assert_eq!(cbf.count(x), 0);
+        }
+        for _ in 0..20 {
+            for x in 0..1000 {
+                cbf.add(x);
+            }
+        }
+        let solid = (0..1000).filter(|&x| cbf.count(x) >= 3).count();
+        assert!(solid >= 990);
+        assert!((0..1000).all(|x| cbf.count(x) <= 7));
+    }
+
+    #[test]
+    fn test_approx_len() {
+        let size = 1 << 20;
+        let n_hashes = 4;
+        let cbf = CountingBloomFilter::new(size, n_hashes);
+        assert_eq!(cbf.approx_len(), 0);
+        for x in 0..1000 {
+            cbf.add(x);
+        }
+        for x in 0..500 {
+            cbf.add(x);
+        }
+        assert!(cbf.approx_len() <= 1000);
+        assert!(cbf.approx_len() >= 990);
+        assert!(cbf.approx_fpr() < 1e-4);
+    }
+
     #[test]
     fn test_seed_bloom() {
         let size = 1 << 20;
@@ -0,0 +1,295 @@
+use clap::{Arg, ArgAction, Command, CommandFactory, ValueEnum, ValueHint};
+use serde_json::{json, Value};
+use std::any::TypeId;
+
+fn is_flag(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse)
+}
+
+/// Returns the JSON type of the values of an argument,
+/// or `enum` if they are restricted and `file` if they are paths to input files.
+fn value_type(arg: &Arg) -> &'static str {
+    let parser = arg.get_value_parser();
+    let type_id = parser.type_id();
+    if is_flag(arg) {
+        "boolean"
+    } else if !arg.get_possible_values().is_empty() {
+        "enum"
+    } else if arg.get_value_hint() == ValueHint::FilePath {
+        "file"
+    } else if [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+    ]
+    .iter()
+    .any(|id| type_id == *id)
+    {
+        "integer"
+    } else if type_id == TypeId::of::<f64>() {
+        "number"
+    } else {
+        "string"
+    }
+}
+
+fn describe_arg(arg: &Arg) -> Value {
+    let defaults: Vec<_> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy())
+        .collect();
+    json!({
+        "id": arg.get_id().as_str(),
+        "long": arg.get_long(),
+        "short": arg.get_short().map(String::from),
+        "positional": arg.is_positional(),
+        "help": arg.get_help().map(ToString::to_string),
+        "type": value_type(arg),
+        "value_name": arg.get_value_names().and_then(|names| names.first()).map(ToString::to_string),
+        "possible_values": arg
+            .get_possible_values()
+            .iter()
+            .filter(|_| !is_flag(arg))
+            .map(|value| value.get_name().to_owned())
+            .collect::<Vec<_>>(),
+        "default": defaults.first().filter(|_| !is_flag(arg)),
+        "required": arg.is_required_set(),
+        "multiple": matches!(arg.get_action(), ArgAction::Append),
+    })
+}
+
+/// Describes a command, its arguments and its subcommands as JSON,
+/// so that wrappers can be generated from the command line interface.
+pub fn describe(command: &Command) -> Value {
+    json!({
+        "name": command.get_name(),
+        "version": command.get_version(),
+        "about": command.get_about().map(ToString::to_string),
+        "args": command
+            .get_arguments()
+            .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+            .map(describe_arg)
+            .collect::<Vec<_>>(),
+        "subcommands": command
+            .get_subcommands()
+            .filter(|subcommand| subcommand.get_name() != "help")
+            .map(describe)
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DescriptorArgs {
+    /// Workflow system for which the tool descriptor is generated
+    #[arg(short, long, value_enum)]
+    format: DescriptorFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DescriptorFormat {
+    /// Common Workflow Language CommandLineTool (YAML)
+    Cwl,
+    /// Galaxy tool wrapper (XML)
+    Galaxy,
+}
+
+/// Prints a descriptor of the correction command for a workflow system.
+pub fn descriptor(args: DescriptorArgs) {
+    let schema = describe(&crate::Cli::command());
+    match args.format {
+        DescriptorFormat::Cwl => print!("{}", cwl(&schema)),
+        DescriptorFormat::Galaxy => print!("{}", galaxy(&schema)),
+    }
+}
+
+/// Returns the arguments exposed by tool descriptors, leaving out the ones that control the CLI,
+/// the resources managed by the workflow system, and the paths of outputs (string arguments).
+fn wrapped_args(schema: &Value) -> impl Iterator<Item = &Value> {
+    schema["args"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|arg| {
+            let id = arg["id"].as_str().unwrap_or_default();
+            !["describe_json", "threads", "metrics_port", "no_atomic"].contains(&id)
+                && arg["type"] != "string"
+        })
+}
+
+/// Quotes a string for YAML, using the JSON syntax which YAML accepts.
+fn yaml(value: &Value) -> String {
+    serde_json::to_string(&value.as_str().unwrap_or_default()).unwrap()
+}
+
+fn cwl(schema: &Value) -> String {
+    let output = "$(inputs.input.nameroot).cor$(inputs.input.nameext)";
+    let mut out = format!(
+        "#!/usr/bin/env cwl-runner\n\
+         cwlVersion: v1.2\n\
+         class: CommandLineTool\n\
+         baseCommand: {}\n\
+         requirements:\n  InlineJavascriptRequirement: {{}}\n\
+         arguments:\n  - prefix: --output\n    valueFrom: {output}\n  \
+         - prefix: --threads\n    valueFrom: $(runtime.cores)\n\
+         inputs:\n",
+        schema["name"].as_str().unwrap_or_default(),
+    );
+    for arg in wrapped_args(schema) {
+        let item = match arg["type"].as_str().unwrap_or_default() {
+            "file" => "File",
+            "boolean" => "boolean",
+            "integer" => "long",
+            "number" => "double",
+            _ => "string",
+        };
+        let id = arg["id"].as_str().unwrap_or_default();
+        out += &format!("  {id}:\n");
+        let binding = if arg["positional"] == true {
+            "position: 1".to_owned()
+        } else {
+            format!("prefix: --{}", arg["long"].as_str().unwrap_or_default())
+        };
+        // Tool descriptors correct a single input, whose output is named after it.
+        if arg["multiple"] == true && arg["positional"] != true {
+            out += &format!(
+                "    type:\n      - \"null\"\n      - type: array\n        items: {item}\n        \
+                 inputBinding:\n          {binding}\n"
+            );
+        } else {
+            if arg["type"] == "enum" {
+                let symbols: Vec<_> = arg["possible_values"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(yaml)
+                    .collect();
+                out += &format!(
+                    "    type:\n      - \"null\"\n      - type: enum\n        symbols: [{}]\n",
+                    symbols.join(", ")
+                );
+            } else if arg["positional"] == true {
+                out += &format!("    type: {item}\n");
+            } else {
+                out += &format!("    type: {item}?\n");
+            }
+            out += &format!("    inputBinding:\n      {binding}\n");
+        }
+        out += &format!("    doc: {}\n", yaml(&arg["help"]));
+    }
+    out +=
+        &format!("outputs:\n  output:\n    type: File\n    outputBinding:\n      glob: {output}\n");
+    out
+}
+
+/// Escapes a string for XML attributes and text.
+fn xml(value: &Value) -> String {
+    value
+        .as_str()
+        .unwrap_or_default()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn galaxy(schema: &Value) -> String {
+    let name = schema["name"].as_str().unwrap_or_default();
+    let mut command =
+        format!("{name} '$input' --output '$output' --threads \\${{GALAXY_SLOTS:-1}}\n");
+    let mut params = String::new();
+    for arg in wrapped_args(schema) {
+        let id = arg["id"].as_str().unwrap_or_default();
+        let label = xml(&arg["help"]);
+        if arg["positional"] == true {
+            params += &format!(
+                "        <param name=\"{id}\" type=\"data\" format=\"fasta\" label=\"{label}\"/>\n"
+            );
+            continue;
+        }
+        let flag = format!("--{}", arg["long"].as_str().unwrap_or_default());
+        let default = &arg["default"];
+        match arg["type"].as_str().unwrap_or_default() {
+            "boolean" => {
+                params += &format!(
+                    "        <param argument=\"{flag}\" type=\"boolean\" truevalue=\"{flag}\" falsevalue=\"\" checked=\"false\" label=\"{label}\"/>\n"
+                );
+                command += &format!("${id}\n");
+                continue;
+            }
+            "enum" => {
+                params += &format!(
+                    "        <param argument=\"{flag}\" type=\"select\" label=\"{label}\">\n"
+                );
+                for value in arg["possible_values"].as_array().into_iter().flatten() {
+                    let selected = if value == default {
+                        " selected=\"true\""
+                    } else {
+                        ""
+                    };
+                    params += &format!(
+                        "            <option value=\"{0}\"{selected}>{0}</option>\n",
+                        xml(value)
+                    );
+                }
+                params += "        </param>\n";
+            }
+            kind => {
+                let kind = match kind {
+                    "file" => "data\" format=\"txt",
+                    "integer" => "integer",
+                    "number" => "float",
+                    _ => "text",
+                };
+                let value = if default.is_null() {
+                    " optional=\"true\"".to_owned()
+                } else {
+                    format!(" value=\"{}\"", xml(default))
+                };
+                params += &format!(
+                    "        <param argument=\"{flag}\" type=\"{kind}\"{value} label=\"{label}\"/>\n"
+                );
+            }
+        }
+        command += &format!("#if str(${id}):\n  {flag} '${id}'\n#end if\n");
+    }
+    format!(
+        "<tool id=\"{name}\" name=\"{name}\" version=\"{}\">\n    \
+         <command detect_errors=\"exit_code\"><![CDATA[\n{command}    ]]></command>\n    \
+         <inputs>\n{params}    </inputs>\n    \
+         <outputs>\n        <data name=\"output\" format_source=\"input\"/>\n    </outputs>\n\
+         </tool>\n",
+        xml(&schema["version"])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_types() {
+        let schema = describe(&crate::Cli::command());
+        let arg_type = |id: &str| {
+            schema["args"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|arg| arg["id"] == id)
+                .map(|arg| arg["type"].as_str().unwrap().to_owned())
+        };
+        assert_eq!(arg_type("input").as_deref(), Some("file"));
+        assert_eq!(arg_type("abundance").as_deref(), Some("integer"));
+        assert_eq!(arg_type("prescreen").as_deref(), Some("number"));
+        assert_eq!(arg_type("stranded").as_deref(), Some("boolean"));
+        assert_eq!(arg_type("shard_by").as_deref(), Some("enum"));
+        assert_eq!(arg_type("output").as_deref(), Some("string"));
+        assert!(schema["subcommands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|subcommand| subcommand["name"] == "bin"));
+    }
+}
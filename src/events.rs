@@ -0,0 +1,68 @@
+use crate::correction::{common_ends, Stats};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Lists the edits turning `original` into `corrected`: each substituted base when both have the
+/// same length, or else the region between their common prefix and suffix.
+fn edits(original: &[u8], corrected: &[u8]) -> Vec<Value> {
+    let edit = |pos: usize, from: &[u8], to: &[u8]| {
+        json!({
+            "pos": pos,
+            "from": String::from_utf8_lossy(from),
+            "to": String::from_utf8_lossy(to),
+        })
+    };
+    if original.len() == corrected.len() {
+        return (0..original.len())
+            .filter(|&i| original[i] != corrected[i])
+            .map(|i| edit(i, &original[i..=i], &corrected[i..=i]))
+            .collect();
+    }
+    let (prefix, suffix) = common_ends(original, corrected);
+    vec![edit(
+        prefix,
+        &original[prefix..original.len() - suffix],
+        &corrected[prefix..corrected.len() - suffix],
+    )]
+}
+
+/// Describes the correction of a read as a single line of JSON, for the `--events` stream.
+/// The confidence is the fraction of solid k-mers in the corrected read.
+pub fn read_event(
+    id: &[u8],
+    original: &[u8],
+    corrected: &[u8],
+    stats: &Stats,
+    confidence: f64,
+    elapsed: Duration,
+) -> String {
+    json!({
+        "id": String::from_utf8_lossy(id),
+        "edits": edits(original, corrected),
+        "errors": stats.errors,
+        "corrections": stats.corrections,
+        "confidence": confidence,
+        "time_us": elapsed.as_micros() as u64,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edits() {
+        assert_eq!(
+            edits(b"ACGTACGT", b"ACCTACGA"),
+            vec![
+                json!({"pos": 2, "from": "G", "to": "C"}),
+                json!({"pos": 7, "from": "T", "to": "A"}),
+            ]
+        );
+        assert_eq!(
+            edits(b"ACGNNTACGT", b"ACGTACGT"),
+            vec![json!({"pos": 3, "from": "NN", "to": ""})]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::lock::RwLock;
+use ahash::RandomState;
+use core::hash::Hash;
+use std::collections::HashMap;
+
+const NIL: usize = usize::MAX;
+
+struct Node<T> {
+    key: T,
+    count: u32,
+    complete: bool,
+    prev: usize,
+    next: usize,
+}
+
+/// Capacity-bounded exact counter with least-recently-used eviction.
+///
+/// Counts of evicted items are lost, so an item inserted after an eviction may
+/// have been counted and evicted before: its count is then only a lower bound,
+/// and it is reported as incomplete.
+struct LruCounter<T> {
+    capacity: usize,
+    map: HashMap<T, usize, RandomState>,
+    nodes: Vec<Node<T>>,
+    head: usize,
+    tail: usize,
+    evicted: bool,
+}
+
+impl<T: Hash + Eq + Copy> LruCounter<T> {
+    fn new(capacity: usize, hash_builder: RandomState) -> Self {
+        Self {
+            capacity,
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+            nodes: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+            evicted: false,
+        }
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.nodes[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.nodes[next].prev = prev;
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = NIL;
+        self.nodes[i].next = self.head;
+        if self.head != NIL {
+            self.nodes[self.head].prev = i;
+        }
+        self.head = i;
+        if self.tail == NIL {
+            self.tail = i;
+        }
+    }
+
+    fn add(&mut self, key: T) {
+        if self.capacity == 0 {
+            self.evicted = true;
+            return;
+        }
+        if let Some(&i) = self.map.get(&key) {
+            self.nodes[i].count = self.nodes[i].count.saturating_add(1);
+            self.unlink(i);
+            self.push_front(i);
+            return;
+        }
+        let node = Node {
+            key,
+            count: 1,
+            complete: !self.evicted,
+            prev: NIL,
+            next: NIL,
+        };
+        let i = if self.nodes.len() < self.capacity {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        } else {
+            let i = self.tail;
+            self.unlink(i);
+            self.map.remove(&self.nodes[i].key);
+            self.evicted = true;
+            self.nodes[i] = node;
+            i
+        };
+        self.map.insert(key, i);
+        self.push_front(i);
+    }
+
+    fn get(&self, key: &T) -> Option<(u32, bool)> {
+        self.map.get(key).map(|&i| {
+            let node = &self.nodes[i];
+            (node.count, node.complete)
+        })
+    }
+}
+
+/// Thread-safe exact counter for a bounded number of items, split into shards
+/// that evict their least recently counted items when full.
+pub struct ExactCounter<T> {
+    shard_shift: usize,
+    shards: Box<[RwLock<LruCounter<T>>]>,
+    hash_builder: RandomState,
+}
+
+impl<T: Hash + Eq + Copy> ExactCounter<T> {
+    pub fn new_with_seed_and_shard_amount(capacity: usize, seed: u64, shard_amount: usize) -> Self {
+        let shard_amount = shard_amount.next_power_of_two();
+        let shard_shift = shard_amount.trailing_zeros() as usize;
+        let shard_capacity = capacity.div_ceil(shard_amount);
+        let hash_builder = RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3);
+        Self {
+            shard_shift,
+            shards: (0..shard_amount)
+                .map(|_| RwLock::new(LruCounter::new(shard_capacity, hash_builder.clone())))
+                .collect(),
+            hash_builder,
+        }
+    }
+
+    fn shard(&self, x: &T) -> &RwLock<LruCounter<T>> {
+        let h = self.hash_builder.hash_one(x);
+        &self.shards[h.checked_shr(64 - self.shard_shift as u32).unwrap_or(0) as usize]
+    }
+
+    pub fn add(&self, x: T) {
+        self.shard(&x).write().add(x);
+    }
+
+    /// Returns the exact count of `x`, if it is tracked and was never evicted.
+    pub fn count(&self, x: T) -> Option<u32> {
+        match self.shard(&x).read().get(&x) {
+            Some((count, true)) => Some(count),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact() {
+        let counter = ExactCounter::new_with_seed_and_shard_amount(100, 42, 1);
+        for x in 0..10 {
+            for _ in 0..x {
+                counter.add(x);
+            }
+        }
+        for x in 1..10 {
+            assert_eq!(counter.count(x), Some(x));
+        }
+        assert_eq!(counter.count(0), None);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let counter = ExactCounter::new_with_seed_and_shard_amount(2, 42, 1);
+        counter.add(1);
+        counter.add(2);
+        counter.add(1);
+        counter.add(3);
+        assert_eq!(counter.count(1), Some(2));
+        assert_eq!(counter.count(2), None);
+        assert_eq!(counter.count(3), Some(1));
+        counter.add(2);
+        assert_eq!(counter.count(2), None);
+    }
+}
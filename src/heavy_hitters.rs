@@ -0,0 +1,137 @@
+use crate::lock::RwLock;
+use ahash::RandomState;
+use core::hash::Hash;
+use std::collections::{BTreeSet, HashMap};
+
+/// Space-Saving sketch of the most frequent items, tracking at most `capacity` items.
+///
+/// When full, a new item replaces the least counted one and inherits its count,
+/// which is recorded as the maximum overestimation of the count of the new item.
+struct SpaceSaving<T> {
+    capacity: usize,
+    counts: HashMap<T, (u64, u64), RandomState>,
+    order: BTreeSet<(u64, T)>,
+}
+
+impl<T: Hash + Ord + Copy> SpaceSaving<T> {
+    fn new(capacity: usize, hash_builder: RandomState) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+            order: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, key: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((count, _)) = self.counts.get_mut(&key) {
+            self.order.remove(&(*count, key));
+            *count += 1;
+            self.order.insert((*count, key));
+            return;
+        }
+        let error = if self.counts.len() < self.capacity {
+            0
+        } else {
+            let (min_count, evicted) = self.order.pop_first().unwrap();
+            self.counts.remove(&evicted);
+            min_count
+        };
+        self.counts.insert(key, (error + 1, error));
+        self.order.insert((error + 1, key));
+    }
+
+    fn top(&self, n: usize) -> impl Iterator<Item = (T, u64, u64)> + '_ {
+        self.order
+            .iter()
+            .rev()
+            .take(n)
+            .map(|&(count, key)| (key, count, self.counts[&key].1))
+    }
+}
+
+/// Thread-safe sketch of the most frequent items, split into shards
+/// that each run a Space-Saving sketch on their share of the items.
+pub struct HeavyHitters<T> {
+    shard_shift: usize,
+    shards: Box<[RwLock<SpaceSaving<T>>]>,
+    hash_builder: RandomState,
+}
+
+impl<T: Hash + Ord + Copy> HeavyHitters<T> {
+    /// Creates a sketch tracking up to `shard_capacity` items in each shard.
+    pub fn new_with_seed_and_shard_amount(
+        shard_capacity: usize,
+        seed: u64,
+        shard_amount: usize,
+    ) -> Self {
+        let shard_amount = shard_amount.next_power_of_two();
+        let shard_shift = shard_amount.trailing_zeros() as usize;
+        let hash_builder = RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3);
+        Self {
+            shard_shift,
+            shards: (0..shard_amount)
+                .map(|_| RwLock::new(SpaceSaving::new(shard_capacity, hash_builder.clone())))
+                .collect(),
+            hash_builder,
+        }
+    }
+
+    fn shard(&self, x: &T) -> &RwLock<SpaceSaving<T>> {
+        let h = self.hash_builder.hash_one(x);
+        &self.shards[h.checked_shr(64 - self.shard_shift as u32).unwrap_or(0) as usize]
+    }
+
+    pub fn add(&self, x: T) {
+        self.shard(&x).write().add(x);
+    }
+
+    /// Returns the `n` most frequent items by decreasing estimated count,
+    /// with their estimated count and its maximum overestimation.
+    pub fn top(&self, n: usize) -> Vec<(T, u64, u64)> {
+        let mut top: Vec<_> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.read().top(n).collect::<Vec<_>>())
+            .collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heavy_hitters() {
+        let sketch = HeavyHitters::new_with_seed_and_shard_amount(100, 42, 4);
+        for x in 0..10u64 {
+            for _ in 0..(10 * x) {
+                sketch.add(x);
+            }
+        }
+        assert_eq!(sketch.top(3), vec![(9, 90, 0), (8, 80, 0), (7, 70, 0)]);
+    }
+
+    #[test]
+    fn test_space_saving() {
+        let sketch = HeavyHitters::new_with_seed_and_shard_amount(20, 42, 1);
+        for _ in 0..10 {
+            sketch.add(0);
+        }
+        for x in 1..100 {
+            sketch.add(x);
+        }
+        for _ in 0..20 {
+            sketch.add(100);
+        }
+        let top = sketch.top(2);
+        assert_eq!(top[0].0, 100);
+        assert!(top[0].1 - top[0].2 <= 20 && 20 <= top[0].1);
+        assert_eq!(top[1], (0, 10, 0));
+    }
+}
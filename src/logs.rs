@@ -0,0 +1,101 @@
+use crate::panic_message;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Whether a panic of this thread is caught and reported as a failed read.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Switches the warnings and errors printed on the standard error to one JSON object per line,
+/// including the fatal errors reported by panics.
+pub fn enable_json() {
+    JSON_LOGS.store(true, Ordering::Relaxed);
+}
+
+/// Prints the panics, as JSON objects with [`enable_json`], except those caught for a single read
+/// by [`catch_read_panic`] which are reported as failed reads instead.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if CATCHING.get() {
+            return;
+        }
+        if JSON_LOGS.load(Ordering::Relaxed) {
+            let message = panic_message(info.payload());
+            let location = info.location().map(|location| location.to_string());
+            eprintln!(
+                "{}",
+                entry("error", "panic", &message, json!({ "location": location }))
+            );
+        } else {
+            default(info);
+        }
+    }));
+}
+
+/// Runs `f` on a single read, catching its panic for the caller to report the read as failed.
+pub fn catch_read_panic<R>(f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    CATCHING.set(true);
+    let result = catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(false);
+    result
+}
+
+fn entry(level: &str, kind: &str, message: &str, fields: Value) -> Value {
+    let mut entry = json!({ "level": level, "kind": kind, "message": message });
+    if let Value::Object(fields) = fields {
+        entry.as_object_mut().unwrap().extend(fields);
+    }
+    entry
+}
+
+/// Prints a warning of the given `kind`, with additional `fields` in JSON logs.
+pub fn warn(kind: &str, message: impl Display, fields: Value) {
+    if JSON_LOGS.load(Ordering::Relaxed) {
+        eprintln!("{}", entry("warning", kind, &message.to_string(), fields));
+    } else {
+        eprintln!("Warning: {message}");
+    }
+}
+
+/// Prints a recoverable error of the given `kind` that affected a single read.
+pub fn read_error(kind: &str, id: &[u8], message: impl Display) {
+    let id = String::from_utf8_lossy(id);
+    if JSON_LOGS.load(Ordering::Relaxed) {
+        let message = message.to_string();
+        eprintln!("{}", entry("error", kind, &message, json!({ "read": id })));
+    } else {
+        eprintln!("Read {id}: {message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        assert_eq!(
+            entry("warning", "saturated_filter", "full", json!({ "fpr": 0.5 })),
+            json!({ "level": "warning", "kind": "saturated_filter", "message": "full", "fpr": 0.5 })
+        );
+        assert_eq!(
+            entry("error", "panic", "oops", Value::Null),
+            json!({ "level": "error", "kind": "panic", "message": "oops" })
+        );
+    }
+
+    #[test]
+    fn test_catch_read_panic() {
+        let outcome = catch_read_panic(|| panic!("oops"));
+        assert_eq!(panic_message(outcome.unwrap_err().as_ref()), "oops");
+        assert!(!CATCHING.get());
+        assert_eq!(catch_read_panic(|| 1).unwrap(), 1);
+    }
+}
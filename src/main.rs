@@ -1,20 +1,73 @@
 #![allow(dead_code)]
+mod adapters;
+mod ambiguous;
+mod assembly;
+mod bam;
+mod bench;
+mod binning;
 mod bloom;
+mod cardinality;
 mod correction;
 mod dashbloom;
+mod describe;
+mod events;
+mod exact;
+mod heavy_hitters;
 mod kmer;
 mod lock;
+mod logs;
+mod metrics;
 mod minimizer;
 mod mutation;
+mod normalize;
+mod output;
+mod pairs;
 mod reads;
-use clap::Parser;
-use correction::{correct, Stats};
-use dashbloom::CountingBloomFilter;
+mod selection;
+mod spectrum;
+mod spill;
+mod sra;
+mod stats;
+mod substitution;
+#[cfg(test)]
+mod testing;
+mod titration;
+use adapters::infer_adapters;
+use ahash::RandomState;
+use ambiguous::{is_ambiguous, is_line_break, NPolicy, NucHandling};
+use assembly::LocalAssembler;
+use bam::{alignment_format, fastq_filename};
+use cardinality::{optimal_hashes, HyperLogLog};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use correction::{
+    changed_bases, comparable_bases, correct, run_kmers, sampled_weak_fraction, solidities, Stats,
+};
+use dashbloom::{BloomFilter, CountingBloomFilter};
+use exact::ExactCounter;
+use heavy_hitters::HeavyHitters;
 use kmer::{Base, Kmer, RawKmer};
+use metrics::Metrics;
 use minimizer::MinimizerQueue;
-use reads::{BaseRecord, Fasta, ReadProcess};
-use std::fs::{metadata, File};
-use std::io::{BufWriter, Write};
+use output::{write_done_file, Codec, FinishWrite, Outputs, COMPRESSION_EXTENSIONS};
+use reads::{
+    content_size, expand_inputs, is_fastq, is_remote, open_files, remote_filename, Bam, BaseRecord,
+    Commented, Fasta, Fastq, ReadProcess, Subsample, Truncations, STALL_WARNING, STDIO,
+};
+use selection::{mean_quality, ReadSelection};
+use serde_json::{json, Value};
+use spectrum::Spectrum;
+use stats::{merge_threads, PerThreadStats};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{create_dir_all, read_to_string};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use substitution::SubstitutionProfile;
 
 // Loads runtime-provided constants for which declarations
 // will be generated at `$OUT_DIR/constants.rs`.
@@ -25,125 +78,2041 @@ pub mod constants {
 use constants::{K, KT, M, MT};
 const W: usize = K - M + 1;
 
+/// Range of abundance ratios, relative to the read coverage,
+/// at which flanking k-mers are considered heterozygous.
+const HET_RATIO_MIN: f64 = 0.3;
+const HET_RATIO_MAX: f64 = 0.7;
+
+/// Number of items tracked by each shard of heavy-hitters sketches for each reported item,
+/// the extra slots absorbing the churn of less frequent items.
+const HEAVY_HITTERS_SLACK: usize = 8;
+
+/// Ratio to the coverage peak above which frequent k-mers are considered adapter candidates.
+const ADAPTER_COVERAGE_RATIO: u64 = 10;
+/// Estimated false positive rate above which a counting filter is reported as saturated.
+const SATURATED_FPR: f64 = 0.1;
+/// Exit code of the runs that skipped a truncated record at the end of their input.
+const TRUNCATED_INPUT_EXIT_CODE: i32 = 3;
+/// Number of hashes of the filters whose number of items is not estimated.
+const DEFAULT_HASHES: usize = 3;
+/// Maximum number of hashes chosen for the counting filters, beyond which queries slow down
+/// for a negligible gain in false positive rate.
+const MAX_HASHES: usize = 8;
+/// Size (in bytes) of the sample of the input from which the numbers of distinct items are estimated.
+const CARDINALITY_SAMPLE_SIZE: f64 = 1e8;
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+    /// Print the schema of the command line interface as JSON and exit
+    #[arg(long)]
+    describe_json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Assign reads to bins by dominant minimizer or by reference k-mer sets
+    Bin(binning::BinArgs),
+    /// Drop reads whose median k-mer abundance exceeds a target coverage
+    Normalize(normalize::NormalizeArgs),
+    /// Generate a tool descriptor for a workflow system from the command line interface
+    Descriptor(describe::DescriptorArgs),
+    /// Measure the throughput of both passes on synthetic reads for several numbers of threads
+    Bench(bench::BenchArgs),
+    /// Report how many reads would have weak regions and how many would be correctable for a ladder of abundances
+    Titrate(titration::TitrateArgs),
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
-    /// Input file (.fasta, .fa)
-    input: String,
-    /// Output file (defaults to <input>.cor.<ext>)
+    /// Input files (.fasta, .fa, .fastq, .fq, .bam, .sam, optionally compressed with gzip, bzip2, xz or zstd), directories or glob patterns of such files, `-` for the standard input, URLs (http://, https://, s3://) or run accessions (SRR…),
+    /// whose k-mers are counted together before correcting each of them
+    #[arg(required_unless_present = "describe_json", value_hint = ValueHint::FilePath)]
+    input: Vec<String>,
+    /// Output file, `-` for the standard output, with a single input (defaults to <input>.cor.<ext> for each input,
+    /// or to the standard output when reading the standard input)
     #[arg(short, long)]
     output: Option<String>,
+    /// Directory in which the default outputs (<input>.cor.<ext>) are written instead of next to the inputs
+    #[arg(long, conflicts_with = "output", value_hint = ValueHint::DirPath)]
+    outdir: Option<String>,
+    /// Name of the output of each input, where {stem} and {ext} stand for the name of the input without
+    /// its extension and for its extension (e.g. {stem}.corrected.{ext})
+    #[arg(long, conflicts_with = "output")]
+    output_template: Option<String>,
     /// Number of threads (defaults to all available threads)
     #[arg(short, long)]
     threads: Option<usize>,
-    /// Memory (in MB) allocated to Bloom filters (defaults to input size)
+    /// Memory (in MB) allocated to Bloom filters (defaults to the decompressed input size, or to its subsampled fraction)
     #[arg(short, long)]
     memory: Option<usize>,
     /// Abundance above which k-mers are solid
     #[arg(short, long, default_value_t = 5)]
     abundance: u8,
-    /// Number of hashes used in Bloom filters
-    #[arg(short = 'H', long, default_value_t = 3)]
-    hashes: usize,
+    /// Number of hashes used in Bloom filters (defaults to the number minimizing the false positive rate
+    /// of each counting filter for its estimated number of distinct items, and to 3 for the other filters)
+    #[arg(short = 'H', long)]
+    hashes: Option<usize>,
+    /// Memory (in MB) allocated to the filter of minimizers, whose space is much smaller than that of k-mers,
+    /// the rest going to the filter of k-mers (defaults to half of the memory)
+    #[arg(long)]
+    min_memory: Option<usize>,
+    /// Number of hashes used in the filter of minimizers (defaults to the number of hashes of the other filters if given)
+    #[arg(long)]
+    min_hashes: Option<usize>,
+    /// Count with 2-bit probabilistic counters, resolving abundances to 0, 1, 3 or 7 (in expectation),
+    /// to fit 4 times more counters in memory at the cost of misclassifying k-mers near the threshold
+    #[arg(long)]
+    low_memory: bool,
+    /// Count k-mers exactly by spilling their super-k-mers into temporary files in this directory,
+    /// counted one bucket at a time, and keep only the solid k-mers in memory (datasets larger than the memory)
+    #[arg(long, value_hint = ValueHint::DirPath, conflicts_with_all = [
+        "low_memory", "compensate", "exact_above", "heavy_hitters", "min_relative_abundance",
+        "end_ramp", "protect_het", "max_anchor_abundance", "repeat_fraction", "spectrum",
+    ])]
+    spill_dir: Option<String>,
+    /// Number of temporary files used with --spill-dir, bounding the memory used to count each of them
+    #[arg(long, default_value_t = 256, requires = "spill_dir")]
+    spill_buckets: usize,
     /// Seed used for hash functions
     #[arg(short, long, default_value_t = 101010)]
     seed: u64,
+    /// Count k-mers on the forward strand only instead of merging both strands (directional protocols)
+    #[arg(long)]
+    stranded: bool,
+    /// Size (in KB) of the batches of reads processed by each thread
+    #[arg(long, default_value_t = 64)]
+    batch_size: usize,
+    /// Number of output files to distribute corrected reads into
+    #[arg(long, visible_alias = "split-output", default_value_t = 1)]
+    output_shards: usize,
+    /// Strategy used to assign reads to output shards
+    #[arg(long, value_enum, default_value_t = ShardBy::RoundRobin)]
+    shard_by: ShardBy,
+    /// Treat the inputs as interleaved pairs (alternating mates), which are written in the same order
+    #[arg(long, conflicts_with = "drop_unselected")]
+    interleaved: bool,
+    /// Write the reads in their input order instead of their completion order
+    #[arg(long)]
+    ordered: bool,
+    /// Only count and correct this fraction of the reads, selected by hashing their name with the seed
+    /// (to quickly tune the parameters on a large dataset)
+    #[arg(long, value_name = "FRACTION")]
+    subsample: Option<f64>,
+    /// Preview a run by only correcting this fraction of the reads (e.g. 1%), without writing them,
+    /// and extrapolating the stats and the duration to all the reads
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction, conflicts_with_all = ["subsample", "spectrum"])]
+    quick: Option<f64>,
+    /// Leave reads uncorrected when their correction takes longer than this (in ms), and warn when the
+    /// ordered output waits longer than this on the same reads (instead of 60 s)
+    #[arg(long)]
+    max_time_per_read_ms: Option<u64>,
+    /// Only detect weak regions and report stats, writing the reads unchanged
+    #[arg(long)]
+    detect_only: bool,
+    /// Handling of the ambiguous bases (e.g. N) when counting and correcting
+    #[arg(long, value_enum, default_value_t = NPolicy::SkipKmers)]
+    n_policy: NPolicy,
+    /// Count the IUPAC ambiguity codes (e.g. R, Y) as the first base they stand for, and never edit them
+    #[arg(long)]
+    canonicalize_iupac: bool,
+    /// Count the lowercase (soft-masked) bases as uppercase ones, but never edit them and keep their case
+    #[arg(long)]
+    respect_softmask: bool,
+    /// Also consider solid the k-mers whose abundance reaches this fraction of the read coverage
+    #[arg(long)]
+    min_relative_abundance: Option<f64>,
+    /// Relax the abundance threshold linearly over this many k-mers from each end of the reads
+    #[arg(long)]
+    end_ramp: Option<usize>,
+    /// Fraction of the abundance threshold required at the very ends of the reads with --end-ramp
+    #[arg(long, default_value_t = 0.5)]
+    end_ramp_min: f64,
+    /// Skip weak regions flanked by k-mers at about half the read coverage (heterozygous sites)
+    #[arg(long)]
+    protect_het: bool,
+    /// Do not anchor corrections on k-mers whose abundance reaches this value (repeats)
+    #[arg(long)]
+    max_anchor_abundance: Option<u8>,
+    /// Report the N most frequent solid minimizers and k-mers, and do not anchor corrections on these k-mers
+    #[arg(long, value_name = "N")]
+    heavy_hitters: Option<usize>,
+    /// Report candidate adapters assembled from the most frequent k-mers that exceed the coverage
+    #[arg(long, requires = "heavy_hitters")]
+    infer_adapters: bool,
+    /// Skip the correction of reads whose fraction of weak k-mers, sampled every K-M+1 positions, is at most this value
+    #[arg(long)]
+    prescreen: Option<f64>,
+    /// Only correct the reads whose mean base quality (Phred+33) is below this value, passing the others
+    /// through (e.g. to leave the HiFi reads of a mixed dataset untouched)
+    #[arg(long, value_name = "QUALITY")]
+    only_if_mean_q_below: Option<f64>,
+    /// Leave uncorrected the reads in which a single solid minimizer spans at least this fraction of the k-mers (tandem repeats)
+    #[arg(long)]
+    repeat_fraction: Option<f64>,
+    /// Leave uncorrected the reads whose corrections would change more than this fraction of their bases
+    #[arg(long)]
+    max_changed_fraction: Option<f64>,
+    /// Only correct the reads listed in this file (one name per line)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    include_names: Option<String>,
+    /// Do not correct the reads listed in this file (one name per line)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    exclude_names: Option<String>,
+    /// Ignore the reads shorter than this when counting, and leave them uncorrected
+    #[arg(long, default_value_t = 0)]
+    min_len: usize,
+    /// Ignore the reads longer than this when counting, and leave them uncorrected
+    #[arg(long)]
+    max_len: Option<usize>,
+    /// Drop the reads that are not selected for correction (including by length) instead of passing them through
+    #[arg(long)]
+    drop_unselected: bool,
+    /// Reference of the host or contaminant (FASTA): reads whose k-mers mostly belong to it are left uncorrected
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    host: Option<String>,
+    /// Minimum fraction of k-mers shared with the host reference to leave a read uncorrected
+    #[arg(long, default_value_t = 0.5, requires = "host")]
+    host_fraction: f64,
+    /// Write the host reads to this file instead of passing them through to the output
+    #[arg(long, requires = "host", conflicts_with = "interleaved")]
+    host_output: Option<String>,
+    /// Verify the solidity of k-mers with a Bloom count above this value using exact counts
+    #[arg(long)]
+    exact_above: Option<u8>,
+    /// Maximum number of k-mers counted exactly (least recently seen ones are evicted)
+    #[arg(long, default_value_t = 1_000_000)]
+    exact_capacity: usize,
+    /// Recount the k-mers skipped while their minimizer was not yet solid, when flanked by solid k-mers (low coverage)
+    #[arg(long)]
+    compensate: bool,
+    /// Never consider solid the k-mers of the sequences listed in this file (one per line, e.g. vectors or adapters)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    mask_kmers: Option<String>,
+    /// Choose between equally supported corrections using the substitution probabilities listed in this file
+    /// (one `<preceding base or *> <actual base> <observed base> <probability>` per line)
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    substitution_profile: Option<String>,
+    /// When no solid path corrects a weak region, search one in the local assembly of the reads sharing
+    /// the minimizers of its flanking k-mers (buffering all the reads in memory)
+    #[arg(long)]
+    local_assembly: bool,
+    /// Write the solidity of each k-mer of the reads to this file (`S` for solid, `w` for weak)
+    #[arg(long)]
+    emit_mask: Option<String>,
+    /// Write one JSON object per modified read (id, edits, confidence, time) to this file (.jsonl)
+    #[arg(long)]
+    events: Option<String>,
+    /// Write to this file the names of the mates of the interleaved pairs whose overlapping mates no longer share
+    /// solid k-mers at the same offset after correction (e.g. one mate moved to another repeat copy)
+    #[arg(long, requires = "interleaved", value_hint = ValueHint::FilePath)]
+    discordant_pairs: Option<String>,
+    /// Recount the corrected reads and write the k-mer spectra before/after correction (.tsv)
+    #[arg(long)]
+    spectrum: Option<String>,
+    /// Serve live metrics of the run in the Prometheus format on this port
+    #[arg(long)]
+    metrics_port: Option<u16>,
+    /// Address on which to serve the metrics, use 0.0.0.0 to expose them on every interface
+    #[arg(long, default_value = "127.0.0.1", requires = "metrics_port")]
+    metrics_addr: IpAddr,
+    /// Write the checksums of the outputs to this file once they are complete
+    #[arg(long)]
+    done_file: Option<String>,
+    /// Write the outputs directly instead of writing to <output>.tmp and renaming on success (e.g. for FIFOs)
+    #[arg(long)]
+    no_atomic: bool,
+    /// Overwrite the existing outputs instead of refusing to start
+    #[arg(long)]
+    force: bool,
+    /// Compress the corrected reads in BGZF blocks, which can be indexed (e.g. by `samtools faidx`)
+    #[arg(long)]
+    bgzf: bool,
+    /// Compress the default outputs with this codec, adding its extension to their names
+    /// (an output given with --output is compressed according to its extension)
+    #[arg(long, value_enum, conflicts_with_all = ["output", "bgzf"])]
+    compress: Option<Codec>,
+    /// Compression level of the outputs, from 0 to 9 for gzip, BGZF and xz, from 1 to 9 for bzip2
+    /// and from 1 to 22 for zstd (defaults to 6)
+    #[arg(long)]
+    compress_level: Option<u32>,
+    /// Number of threads compressing the gzip, BGZF and zstd outputs (defaults to the number of threads)
+    #[arg(long)]
+    compress_threads: Option<usize>,
+    /// Print warnings and errors as JSON objects on the standard error, one per line
+    #[arg(long)]
+    json_logs: bool,
+    /// Print the number of reads corrected by each thread and the time it spent on them
+    #[arg(short, long)]
+    verbose: bool,
 }
 
-fn main() {
-    let args = Args::parse();
-    let input_filename = args.input.as_str();
-    let output_filename = if let Some(filename) = args.output {
-        filename
-    } else if let Some((begin, end)) = input_filename.rsplit_once('.') {
-        begin.to_owned() + ".cor." + end
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ShardBy {
+    /// Cycle through the shards in output order
+    RoundRobin,
+    /// Hash the read name, keeping mates (`/1`, `/2`) in the same shard
+    Name,
+    /// Write each read to the shard with the fewest bases so far, balancing their sizes
+    Size,
+}
+
+/// Result of the correction of a read.
+#[derive(Debug, Default)]
+struct CorrectedRead {
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+    stats: Stats,
+    dropped: bool,
+    host: bool,
+    mask: Vec<u8>,
+    event: String,
+    failure: Option<String>,
+    /// Ends of the parts of the sequence when the read is split around its ambiguous bases.
+    parts: Vec<usize>,
+}
+
+impl CorrectedRead {
+    /// Copies the original sequence and qualities to the output.
+    fn pass_through(&mut self, record: &impl BaseRecord) {
+        self.stats = Stats::default();
+        self.seq.clear();
+        self.seq.extend_from_slice(&record.full_seq());
+        self.qual.clear();
+        self.qual
+            .extend_from_slice(&record.opt_full_qual().unwrap_or_default());
+        self.parts.clear();
+    }
+}
+
+/// Writes a corrected read and its qualities in the format of its record, keeping its full header
+/// and the comment of its separator line.
+/// Qualities that do not match the length of the sequence are truncated or padded with the lowest quality.
+fn write_read<W: Write>(
+    writer: &mut W,
+    record: &(impl BaseRecord + Commented),
+    seq: &[u8],
+    qual: &[u8],
+) -> io::Result<()> {
+    write_record(writer, record, record.head(), seq, qual)
+}
+
+/// Writes the `parts` of a read split around its ambiguous bases as separate reads, named after
+/// the read with the number of the part (e.g. `r0_1`, `r0_2`), or the whole read if it was not split.
+fn write_parts<W: Write>(
+    writer: &mut W,
+    record: &(impl BaseRecord + Commented),
+    seq: &[u8],
+    qual: &[u8],
+    parts: &[usize],
+) -> io::Result<()> {
+    if parts.len() <= 1 {
+        return write_read(writer, record, seq, qual);
+    }
+    let (id, desc) = record.head().split_at(record.id_bytes().len());
+    let mut start = 0;
+    for (i, &end) in parts.iter().enumerate() {
+        let head = [id, format!("_{}", i + 1).as_bytes(), desc].concat();
+        let part_qual = qual.get(start..end).unwrap_or_default();
+        write_record(writer, record, &head, &seq[start..end], part_qual)?;
+        start = end;
+    }
+    Ok(())
+}
+
+fn write_record<W: Write>(
+    writer: &mut W,
+    record: &(impl BaseRecord + Commented),
+    head: &[u8],
+    seq: &[u8],
+    qual: &[u8],
+) -> io::Result<()> {
+    match record.opt_qual().is_some() {
+        true => {
+            writer.write_all(b"@")?;
+            writer.write_all(head)?;
+            writer.write_all(b"\n")?;
+            writer.write_all(seq)?;
+            writer.write_all(b"\n+")?;
+            record.write_comment(writer)?;
+            writer.write_all(b"\n")?;
+            let kept = qual.len().min(seq.len());
+            writer.write_all(&qual[..kept])?;
+            writer.write_all(&vec![b'!'; seq.len() - kept])?;
+        }
+        false => {
+            writer.write_all(b">")?;
+            writer.write_all(head)?;
+            writer.write_all(b"\n")?;
+            writer.write_all(seq)?;
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+/// Parses a fraction given either as a number between 0 and 1 or as a percentage (e.g. `1%`).
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0),
+        None => s.parse::<f64>(),
+    }
+    .map_err(|e| e.to_string())?;
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(fraction)
     } else {
-        input_filename.to_owned() + ".cor"
-    };
-    let threads = if let Some(num) = args.threads {
-        num
+        Err(format!("{s} is not a fraction in (0, 1]"))
+    }
+}
+
+/// Extracts the message of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
     } else {
-        std::thread::available_parallelism().map_or(1, |x| x.get())
-    };
-    let shard_amount = threads * 4;
-    let size = if let Some(m) = args.memory {
-        m * 1_000_000 / 2
+        "unknown panic".to_owned()
+    }
+}
+
+/// Returns the index of the shard with the fewest bases, the first one in case of ties.
+fn smallest_shard(shard_bases: &[usize]) -> usize {
+    shard_bases
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, &bases)| bases)
+        .map_or(0, |(i, _)| i)
+}
+
+/// Inserts `tag` before the extension of `filename`, leaving its directories untouched.
+/// The extension of a compressed input is dropped since outputs are written uncompressed.
+fn tagged_filename<S: Display>(filename: &str, tag: S) -> String {
+    templated_filename(filename, &format!("{{stem}}.{tag}.{{ext}}"))
+}
+
+/// Renames `filename` after `template`, in which `{stem}` and `{ext}` stand for its name without
+/// its extension and for its extension, leaving its directories untouched.
+/// The extension of a compressed input is dropped since outputs are written uncompressed.
+fn templated_filename(filename: &str, template: &str) -> String {
+    let path = Path::new(filename);
+    let path = if path
+        .extension()
+        .is_some_and(|ext| ["gz", "bz2", "xz", "zst"].iter().any(|c| ext == *c))
+    {
+        path.with_extension("")
     } else {
-        metadata(input_filename)
-            .expect("Failed to get input size")
-            .len() as usize
-            / 2
+        path.to_path_buf()
+    };
+    let template = match path.extension() {
+        Some(ext) => template.replace("{ext}", &ext.to_string_lossy()),
+        None => template.replace(".{ext}", "").replace("{ext}", ""),
     };
-    let min_counts = CountingBloomFilter::new_with_seed_and_shard_amount(
-        size,
-        args.hashes,
-        args.seed + M as u64,
-        shard_amount,
+    match path.file_stem() {
+        Some(stem) => {
+            let name = template.replace("{stem}", &stem.to_string_lossy());
+            path.with_file_name(name).to_string_lossy().into_owned()
+        }
+        None => template.replace("{stem}", filename),
+    }
+}
+
+/// Returns the names of the `shards` files into which the output `filename` is split.
+fn shard_filenames(filename: &str, shards: usize) -> Vec<String> {
+    if shards == 1 {
+        vec![filename.to_owned()]
+    } else {
+        (0..shards)
+            .map(|i| tagged_output_filename(filename, i))
+            .collect()
+    }
+}
+
+/// Tags a filename like `tagged_filename`, but keeps its compression extension if any,
+/// so that the files derived from a compressed output are compressed as well.
+fn tagged_output_filename<S: Display>(filename: &str, tag: S) -> String {
+    match filename.rsplit_once('.') {
+        Some((name, ext)) if COMPRESSION_EXTENSIONS.contains(&ext) => {
+            format!("{}.{ext}", tagged_filename(name, tag))
+        }
+        _ => tagged_filename(filename, tag),
+    }
+}
+
+/// Strips the mate suffix (`/1` or `/2`) from a read name.
+fn pair_name(id: &[u8]) -> &[u8] {
+    match id {
+        [name @ .., b'/', b'1' | b'2'] => name,
+        _ => id,
+    }
+}
+
+/// Returns the number of threads to use (defaults to all available threads).
+fn thread_count(threads: Option<usize>) -> usize {
+    threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()))
+}
+
+/// Returns `kmer` as is for `stranded` counting, or its canonical form otherwise.
+fn oriented<const N: usize, T: Base, KmerT: Kmer<N, T>>(kmer: KmerT, stranded: bool) -> KmerT {
+    if stranded {
+        kmer
+    } else {
+        kmer.canonical()
+    }
+}
+
+/// State of the scan of the k-mers of a sequence along with their minimizers,
+/// which can be reset to scan another sequence without reallocating its minimizer queue.
+struct KmerMinimizerState {
+    kmer: RawKmer<K, KT>,
+    mmer: RawKmer<M, MT>,
+    queue: MinimizerQueue<W, RawKmer<M, MT>>,
+    len: usize,
+    stranded: bool,
+}
+
+impl KmerMinimizerState {
+    fn new(seed: u64, stranded: bool) -> Self {
+        Self {
+            kmer: RawKmer::new(),
+            mmer: RawKmer::new(),
+            queue: MinimizerQueue::new_with_seed(seed),
+            len: 0,
+            stranded,
+        }
+    }
+
+    /// Starts a new sequence, e.g. the next record or the part of a record following an N.
+    fn reset(&mut self) {
+        self.kmer = RawKmer::new();
+        self.mmer = RawKmer::new();
+        self.queue.clear();
+        self.len = 0;
+    }
+
+    /// Appends a base to the sequence, returning the k-mer ending with it along with its minimizer,
+    /// both oriented for `stranded` counting, once the sequence is K bases long.
+    #[inline]
+    fn push(&mut self, base: MT) -> Option<(RawKmer<K, KT>, RawKmer<M, MT>)> {
+        self.len += 1;
+        if self.len < M {
+            self.mmer = self.mmer.extend(base);
+        } else {
+            self.mmer = self.mmer.append(base);
+            self.queue.insert(oriented(self.mmer, self.stranded));
+        }
+        if self.len < K {
+            self.kmer = self.kmer.extend(base as KT);
+            None
+        } else {
+            self.kmer = self.kmer.append(base as KT);
+            Some((oriented(self.kmer, self.stranded), self.queue.get_min()))
+        }
+    }
+}
+
+/// Iterator over the k-mers of a sequence along with their minimizer, both oriented for `stranded`
+/// counting, and whether this minimizer differs from the one of the previous k-mer.
+struct KmerMinimizerIter<I> {
+    nucs: I,
+    state: KmerMinimizerState,
+    prev_min: Option<RawKmer<M, MT>>,
+}
+
+impl<I> KmerMinimizerIter<I> {
+    fn new(nucs: I, seed: u64, stranded: bool) -> Self {
+        Self {
+            nucs,
+            state: KmerMinimizerState::new(seed, stranded),
+            prev_min: None,
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = &'a u8>> Iterator for KmerMinimizerIter<I> {
+    type Item = (RawKmer<K, KT>, RawKmer<M, MT>, bool);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let nuc = self.nucs.next()?;
+            let Some(base) = MT::from_nuc(nuc) else {
+                // The k-mers overlapping an ambiguous base are skipped.
+                if is_ambiguous(nuc) {
+                    self.state.reset();
+                    self.prev_min = None;
+                }
+                continue;
+            };
+            if let Some((kmer, min)) = self.state.push(base) {
+                let changed = self.prev_min != Some(min);
+                self.prev_min = Some(min);
+                return Some((kmer, min, changed));
+            }
+        }
+    }
+}
+
+/// Calls `f` on each k-mer of `nucs` along with its minimizer, both oriented for `stranded` counting.
+fn for_each_kmer_with_minimizer<
+    'a,
+    I: Iterator<Item = &'a u8>,
+    F: FnMut(RawKmer<K, KT>, RawKmer<M, MT>),
+>(
+    nucs: I,
+    seed: u64,
+    stranded: bool,
+    mut f: F,
+) {
+    for (kmer, min, _) in KmerMinimizerIter::new(nucs, seed, stranded) {
+        f(kmer, min);
+    }
+}
+
+/// Counts the k-mers of a read whose minimizer reaches `min_threshold` in `min_counts`,
+/// each minimizer being counted once per run of consecutive k-mers sharing it.
+fn count_read_kmers<'a>(
+    nucs: impl Iterator<Item = &'a u8>,
+    min_counts: &CountingBloomFilter,
+    kmer_counts: &CountingBloomFilter,
+    min_threshold: u8,
+    seed: u64,
+    stranded: bool,
+) {
+    let mut min_is_solid = false;
+    for (kmer, min, changed) in KmerMinimizerIter::new(nucs, seed, stranded) {
+        if changed {
+            min_is_solid = min_counts.add_and_count(min) >= min_threshold;
+        }
+        if min_is_solid {
+            kmer_counts.add(kmer);
+        }
+    }
+}
+
+/// Estimates the numbers of distinct minimizers and k-mers of the reads of which `reads` is
+/// a sampled `fraction`, extrapolating linearly as most distinct items are erroneous ones.
+fn estimate_cardinalities(
+    reads: impl ReadProcess,
+    fraction: f64,
+    threads: usize,
+    seed: u64,
+    stranded: bool,
+) -> (f64, f64) {
+    let mins = HyperLogLog::new_with_seed(seed + M as u64);
+    let kmers = HyperLogLog::new_with_seed(seed + K as u64);
+    reads.process_par(threads as u32, 32, |nucs| {
+        for (kmer, min, changed) in KmerMinimizerIter::new(nucs, seed + W as u64, stranded) {
+            if changed {
+                mins.insert(min);
+            }
+            kmers.insert(kmer);
+        }
+    });
+    (mins.estimate() / fraction, kmers.estimate() / fraction)
+}
+
+/// Counts the k-mers of `reads` whose minimizer reaches `min_threshold` in `min_counts`,
+/// handling their letters other than ACGT according to `handling`.
+#[allow(clippy::too_many_arguments)]
+fn count_kmers(
+    reads: impl ReadProcess,
+    threads: usize,
+    min_counts: &CountingBloomFilter,
+    kmer_counts: &CountingBloomFilter,
+    min_threshold: u8,
+    seed: u64,
+    stranded: bool,
+    handling: NucHandling,
+) {
+    reads.process_par(threads as u32, 32, |nucs| {
+        count_read_kmers(
+            handling.resolve(nucs.as_slice()).iter(),
+            min_counts,
+            kmer_counts,
+            min_threshold,
+            seed + W as u64,
+            stranded,
+        )
+    });
+}
+
+/// Returns the minimizer shared by the longest run of consecutive k-mers with the length of this
+/// run and the number of k-mers of the sequence, or `None` if the sequence has no k-mer.
+fn dominant_minimizer(
+    nucs: &[u8],
+    seed: u64,
+    stranded: bool,
+) -> Option<(RawKmer<M, MT>, usize, usize)> {
+    let mut best = None;
+    let mut run = 0;
+    let mut kmers = 0;
+    for (_, min, changed) in KmerMinimizerIter::new(nucs.iter(), seed, stranded) {
+        run = if changed { 1 } else { run + 1 };
+        kmers += 1;
+        if best.is_none_or(|(_, best_run)| run > best_run) {
+            best = Some((min, run));
+        }
+    }
+    best.map(|(min, run)| (min, run, kmers))
+}
+
+/// Reads the k-mers of a list of sequences, one per line, oriented for `stranded` counting.
+fn read_kmers(filename: &str, stranded: bool) -> HashSet<RawKmer<K, KT>> {
+    read_to_string(filename)
+        .expect("Failed to read k-mers file")
+        .lines()
+        .flat_map(|line| RawKmer::<K, KT>::iter_from_nucs(line.trim().as_bytes().iter()))
+        .map(|kmer| oriented(kmer, stranded))
+        .collect()
+}
+
+/// Returns the median abundance of the solid k-mers of a resolved read, or 0 if it has none.
+fn median_abundance(
+    nucs: &[u8],
+    kmer_counts: &CountingBloomFilter,
+    threshold: u8,
+    stranded: bool,
+) -> u8 {
+    let mut abundances: Vec<_> = run_kmers(nucs)
+        .map(|kmer: RawKmer<K, KT>| kmer_counts.count(oriented(kmer, stranded)))
+        .filter(|&abundance| abundance >= threshold)
+        .collect();
+    if abundances.is_empty() {
+        return 0;
+    }
+    let mid = abundances.len() / 2;
+    *abundances.select_nth_unstable(mid).1
+}
+
+/// Counts the k-mers of the reads opened by `open_reads` into the empty `min_counts` and
+/// `kmer_counts` like in the first pass, and computes their spectrum.
+#[allow(clippy::too_many_arguments)]
+fn recount_spectrum<R: ReadProcess>(
+    open_reads: impl Fn() -> R,
+    threads: usize,
+    min_counts: &CountingBloomFilter,
+    kmer_counts: &CountingBloomFilter,
+    min_threshold: u8,
+    seed: u64,
+    stranded: bool,
+    handling: NucHandling,
+) -> Spectrum {
+    count_kmers(
+        open_reads(),
+        threads,
+        min_counts,
+        kmer_counts,
+        min_threshold,
+        seed,
+        stranded,
+        handling,
     );
-    let kmer_counts = CountingBloomFilter::new_with_seed_and_shard_amount(
-        size,
-        args.hashes,
-        args.seed + K as u64,
-        shard_amount,
+    kmer_spectrum(open_reads(), threads, kmer_counts, stranded, handling)
+}
+
+/// Computes the k-mer spectrum of `reads` from the abundances in `kmer_counts`,
+/// resolving their letters other than ACGT according to `handling`.
+fn kmer_spectrum(
+    reads: impl ReadProcess,
+    threads: usize,
+    kmer_counts: &CountingBloomFilter,
+    stranded: bool,
+    handling: NucHandling,
+) -> Spectrum {
+    let mut spectrum = Spectrum::default();
+    reads.process_par_result(
+        threads as u32,
+        32,
+        |nucs, abundances: &mut Vec<u8>| {
+            abundances.clear();
+            abundances.extend(
+                run_kmers(&handling.resolve(nucs.as_slice()))
+                    .map(|kmer: RawKmer<K, KT>| kmer_counts.count(oriented(kmer, stranded))),
+            );
+        },
+        |abundances| abundances.iter().for_each(|&a| spectrum.add(a)),
     );
-    let min_threshold = (args.abundance + 1) / 2;
-    let kmer_threshold = args.abundance + 1 - min_threshold;
-    let solid_kmer = |kmer: RawKmer<K, KT>| kmer_counts.count(kmer.canonical()) >= kmer_threshold;
+    spectrum
+}
 
-    let reads = Fasta::from_file(input_filename);
+/// Counts exactly the k-mers of `reads` reaching `above` in `kmer_counts` into `exact_counts`,
+/// resolving their letters other than ACGT according to `handling` like the Bloom counts.
+#[allow(clippy::too_many_arguments)]
+fn count_exact(
+    reads: impl ReadProcess,
+    threads: usize,
+    kmer_counts: &CountingBloomFilter,
+    above: u8,
+    exact_counts: &ExactCounter<RawKmer<K, KT>>,
+    stranded: bool,
+    handling: NucHandling,
+) {
     reads.process_par(threads as u32, 32, |nucs| {
-        let mut kmer = RawKmer::<K, KT>::new();
-        let mut mmer = RawKmer::<M, MT>::new();
-        let mut queue = MinimizerQueue::<W, _>::new_with_seed(args.seed + W as u64);
-        let mut prev_min = RawKmer::<M, MT>::new();
-        let mut min_is_solid = false;
-        for (i, base) in nucs.filter_map(MT::from_nuc).enumerate() {
-            if i < M - 1 {
-                mmer = mmer.extend(base);
-            } else {
-                mmer = mmer.append(base);
-                queue.insert(mmer.canonical());
+        for kmer in run_kmers(&handling.resolve(nucs.as_slice())) {
+            let kmer = oriented(kmer, stranded);
+            if kmer_counts.count(kmer) >= above {
+                exact_counts.add(kmer);
             }
-            if i < K - 1 {
-                kmer = kmer.extend(base as KT);
+        }
+    });
+}
+
+/// Writes into `mask` the solidity of the k-mers of a resolved read (`S` for solid, `w` for weak),
+/// skipping those overlapping an ambiguous base.
+fn solidity_mask(
+    nucs: &[u8],
+    solid: impl Fn(RawKmer<K, KT>) -> bool,
+    prefetch: impl Fn(RawKmer<K, KT>),
+    mask: &mut Vec<u8>,
+) {
+    let kmers: Vec<_> = run_kmers(nucs).collect();
+    mask.clear();
+    mask.extend(solidities(&kmers, solid, prefetch).map(|solid| if solid { b'S' } else { b'w' }));
+}
+
+fn main() {
+    logs::install_panic_hook();
+    let cli = Cli::parse();
+    if cli.describe_json {
+        let schema = describe::describe(&Cli::command());
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        return;
+    }
+    let truncations = Truncations::default();
+    match cli.command {
+        Some(Command::Bin(args)) => binning::bin(args, &truncations),
+        Some(Command::Normalize(args)) => normalize::normalize(args, &truncations),
+        Some(Command::Descriptor(args)) => describe::descriptor(args),
+        Some(Command::Bench(args)) => bench::bench(args),
+        Some(Command::Titrate(args)) => titration::titrate(args, &truncations),
+        None => run(cli.args, &truncations),
+    }
+    if !truncations.is_empty() {
+        std::process::exit(TRUNCATED_INPUT_EXIT_CODE);
+    }
+}
+
+/// Corrects the reads of the inputs, recording in `truncations` the truncated records skipped
+/// at their end.
+fn run(mut args: Args, truncations: &Truncations) {
+    if args.json_logs {
+        logs::enable_json();
+    }
+    // Runs given by accession are streamed from the FASTQ files mirrored by the ENA.
+    args.input = args
+        .input
+        .iter()
+        .flat_map(|input| {
+            if sra::is_accession(input) && !Path::new(input).exists() {
+                sra::resolve_accession(input)
             } else {
-                kmer = kmer.append(base as KT);
-                let min = queue.get_min();
-                if min == prev_min {
-                    if min_is_solid {
-                        kmer_counts.add(kmer.canonical());
-                    }
+                vec![input.clone()]
+            }
+        })
+        .collect();
+    args.input = expand_inputs(&args.input);
+    let first = args.input.first().expect("Missing input file");
+    let batch_size = args.batch_size << 10;
+    if alignment_format(first).is_some() {
+        assert!(
+            args.input
+                .iter()
+                .all(|filename| alignment_format(filename).is_some()),
+            "The input files must be either all alignment files or none of them"
+        );
+        return run_with(args, |filenames| {
+            Bam::from_files_with_batch_size(filenames, batch_size).truncations(truncations)
+        });
+    }
+    let fastq = is_fastq(first);
+    assert!(
+        args.input
+            .iter()
+            .all(|filename| is_fastq(filename) == fastq),
+        "The input files must be either all FASTA or all FASTQ"
+    );
+    if fastq {
+        run_with(args, |filenames| {
+            Fastq::from_files_with_batch_size(filenames, batch_size).truncations(truncations)
+        })
+    } else {
+        run_with(args, |filenames| {
+            Fasta::from_reader_with_batch_size(open_files(filenames), batch_size)
+        })
+    }
+}
+
+/// Corrects the reads of the inputs, opening the inputs and outputs with `open_reads`
+/// which reads several files as one.
+fn run_with<R: ReadProcess>(args: Args, open_reads: impl Fn(&[String]) -> R) {
+    let start = Instant::now();
+    let inputs = &args.input;
+    let fraction = args.quick.or(args.subsample).unwrap_or(1.0);
+    let lengths = args.min_len..=args.max_len.unwrap_or(usize::MAX);
+    assert!(
+        !lengths.is_empty(),
+        "The minimum length must be ≤ the maximum length"
+    );
+    // A quick look still counts all the reads, since the abundances of a sample would be too low
+    // to tell the solid k-mers apart.
+    let counted_fraction = if args.quick.is_some() { 1.0 } else { fraction };
+    // Reads outside the range of lengths are not counted, but still go through the correction
+    // to be passed through (or dropped) like unselected reads.
+    let open_all_reads =
+        |filenames: &[String]| Subsample::new(open_reads(filenames), fraction, args.seed);
+    let open_reads = |filenames: &[String]| {
+        Subsample::new(open_reads(filenames), counted_fraction, args.seed).lengths(lengths.clone())
+    };
+    assert!(
+        !(args.interleaved && args.n_policy == NPolicy::SplitRead),
+        "Interleaved pairs cannot be split around their ambiguous bases"
+    );
+    let handling = NucHandling {
+        policy: args.n_policy,
+        canonicalize_iupac: args.canonicalize_iupac,
+        respect_softmask: args.respect_softmask,
+    };
+    assert!(
+        args.output.is_none() || inputs.len() == 1,
+        "The output file can only be given with a single input file"
+    );
+    let input_outputs: Vec<_> = inputs
+        .iter()
+        .map(|input_filename| {
+            args.output.clone().unwrap_or_else(|| {
+                // Remote inputs are written to the current directory.
+                let name = if is_remote(input_filename) {
+                    remote_filename(input_filename)
                 } else {
-                    min_is_solid = min_counts.add_and_count(min) >= min_threshold;
-                    if min_is_solid {
-                        kmer_counts.add(kmer.canonical());
+                    input_filename
+                };
+                let name = match &args.outdir {
+                    Some(outdir) => {
+                        create_dir_all(outdir).expect("Failed to create output directory");
+                        let file_name = Path::new(name).file_name().unwrap_or_default();
+                        Path::new(outdir)
+                            .join(file_name)
+                            .to_string_lossy()
+                            .into_owned()
                     }
-                    prev_min = min;
+                    None => name.to_owned(),
+                };
+                let name = if alignment_format(input_filename).is_some() {
+                    fastq_filename(&name)
+                } else {
+                    name
+                };
+                let output_filename = if input_filename == STDIO {
+                    return STDIO.to_owned();
+                } else if let Some(template) = &args.output_template {
+                    templated_filename(&name, template)
+                } else {
+                    tagged_filename(&name, "cor")
+                };
+                if args.bgzf {
+                    format!("{output_filename}.gz")
+                } else if let Some(codec) = args.compress {
+                    format!("{output_filename}.{}", codec.extension())
+                } else {
+                    output_filename
                 }
+            })
+        })
+        .collect();
+    let to_files: Vec<_> = input_outputs
+        .iter()
+        .filter(|filename| *filename != STDIO)
+        .collect();
+    assert!(
+        to_files.iter().collect::<HashSet<_>>().len() == to_files.len(),
+        "Several inputs would be written to the same output file"
+    );
+    let to_stdout = input_outputs.iter().any(|filename| filename == STDIO);
+    assert!(
+        !to_stdout || (args.output_shards == 1 && args.spectrum.is_none()),
+        "The standard output cannot be split into shards nor recounted for the spectrum"
+    );
+    let threads = thread_count(args.threads);
+    let mut outputs = Outputs::new(!args.no_atomic)
+        .threads(args.compress_threads.unwrap_or(threads))
+        .level(args.compress_level)
+        .overwrite(args.force);
+    // The outputs are checked before the counting, rather than after hours of it.
+    input_outputs
+        .iter()
+        .filter(|_| args.quick.is_none())
+        .flat_map(|filename| shard_filenames(filename, args.output_shards))
+        .chain(
+            [
+                &args.emit_mask,
+                &args.events,
+                &args.discordant_pairs,
+                &args.host_output,
+                &args.spectrum,
+                &args.done_file,
+            ]
+            .into_iter()
+            .flatten()
+            .cloned(),
+        )
+        .try_for_each(|filename| outputs.check(&filename))
+        .expect("Failed to open output file");
+    let metrics = args.metrics_port.map(|port| {
+        let metrics = Arc::new(Metrics::default());
+        metrics::serve(args.metrics_addr, port, metrics.clone());
+        metrics
+    });
+    let shard_amount = threads * 4;
+    let size = if let Some(m) = args.memory {
+        m * 1_000_000 / 2
+    } else if args.low_memory {
+        // Same number of counters as the 8-bit filters
+        (inputs.iter().map(content_size).sum::<usize>() as f64 * counted_fraction) as usize / 8
+    } else {
+        (inputs.iter().map(content_size).sum::<usize>() as f64 * counted_fraction) as usize / 2
+    };
+    // The memory of the filters is split between minimizers and k-mers.
+    let min_size = args.min_memory.map_or(size, |m| m * 1_000_000);
+    assert!(
+        min_size < 2 * size,
+        "The memory of the filter of minimizers must be less than the memory of the filters"
+    );
+    let kmer_size = 2 * size - min_size;
+    // The solid k-mers counted on disk replace the counting filters, which are left empty.
+    let counting = args.spill_dir.is_none();
+    let default_hashes = args.hashes.unwrap_or(DEFAULT_HASHES);
+    let (min_hashes, kmer_hashes) = match (args.min_hashes.or(args.hashes), args.hashes) {
+        (Some(min_hashes), Some(kmer_hashes)) => (min_hashes, kmer_hashes),
+        // The size of remote inputs is unknown, and sampling them would download them once more.
+        _ if !counting || inputs.iter().any(is_remote) => {
+            (args.min_hashes.unwrap_or(default_hashes), default_hashes)
+        }
+        (min_hashes, kmer_hashes) => {
+            let content = inputs.iter().map(content_size).sum::<usize>() as f64 * counted_fraction;
+            let sample_fraction = (CARDINALITY_SAMPLE_SIZE / content).min(1.0);
+            let (mins, kmers) = estimate_cardinalities(
+                Subsample::new(open_reads(inputs), sample_fraction, args.seed + 1),
+                sample_fraction,
+                threads,
+                args.seed,
+                args.stranded,
+            );
+            // Morris counters take 2 bits instead of 8.
+            let counters = |size| if args.low_memory { size * 4 } else { size };
+            let min_hashes =
+                min_hashes.unwrap_or_else(|| optimal_hashes(counters(min_size), mins, MAX_HASHES));
+            let kmer_hashes = kmer_hashes
+                .unwrap_or_else(|| optimal_hashes(counters(kmer_size), kmers, MAX_HASHES));
+            eprintln!(
+                "~{mins:.0} distinct minimizers and ~{kmers:.0} distinct k-mers estimated, \
+                 using {min_hashes} and {kmer_hashes} hashes in their filters"
+            );
+            (min_hashes, kmer_hashes)
+        }
+    };
+    let new_filter = |size, hashes, seed| {
+        let size = if counting { size } else { 0 };
+        if args.low_memory {
+            CountingBloomFilter::new_morris_with_seed_and_shard_amount(
+                size,
+                hashes,
+                seed,
+                shard_amount,
+            )
+        } else {
+            CountingBloomFilter::new_with_seed_and_shard_amount(size, hashes, seed, shard_amount)
+        }
+    };
+    let min_counts = new_filter(min_size, min_hashes, args.seed + M as u64);
+    let kmer_counts = new_filter(kmer_size, kmer_hashes, args.seed + K as u64);
+    let solid_filter = args.spill_dir.as_deref().map(|dir| {
+        let solid_filter = BloomFilter::new_with_seed_and_shard_amount(
+            size * 16,
+            default_hashes,
+            args.seed + K as u64,
+            shard_amount,
+        );
+        let solid_kmers = spill::count_solid_kmers(
+            open_reads(inputs),
+            Path::new(dir),
+            args.spill_buckets,
+            threads,
+            args.seed,
+            args.stranded,
+            args.abundance as u32,
+            &solid_filter,
+            handling,
+        );
+        eprintln!("{solid_kmers} solid k-mers counted exactly");
+        solid_filter
+    });
+    let min_threshold = args.abundance.div_ceil(2);
+    let kmer_threshold = args.abundance + 1 - min_threshold;
+    if args.low_memory {
+        assert!(
+            kmer_threshold <= 7,
+            "The abundance must be ≤ 13 with --low-memory, whose counts saturate at 7"
+        );
+        logs::warn(
+            "approximate_counts",
+            "--low-memory counts k-mers approximately (0, 1, 3 or 7 occurrences in expectation), \
+             so k-mers whose abundance is close to the threshold may be misclassified",
+            Value::Null,
+        );
+    }
+
+    if solid_filter.is_none() {
+        count_kmers(
+            open_reads(inputs),
+            threads,
+            &min_counts,
+            &kmer_counts,
+            min_threshold,
+            args.seed,
+            args.stranded,
+            handling,
+        );
+    }
+
+    // The first occurrences of each minimizer are not followed by the counting of their k-mers:
+    // replaying the minimizer counts identifies as many occurrences to count again.
+    if args.compensate {
+        let replay_counts = new_filter(min_size, min_hashes, args.seed + M as u64);
+        let recounted = AtomicUsize::new(0);
+        open_reads(inputs).process_par(threads as u32, 32, |nucs| {
+            let mut kmers = Vec::new();
+            let mut mins = Vec::new();
+            let resolved = handling.resolve(nucs.as_slice());
+            for_each_kmer_with_minimizer(
+                resolved.iter(),
+                args.seed + W as u64,
+                args.stranded,
+                |kmer, min| {
+                    kmers.push(kmer);
+                    mins.push(min);
+                },
+            );
+            let solid_flank = |i: Option<usize>| {
+                i.and_then(|i| kmers.get(i))
+                    .is_some_and(|&kmer| kmer_counts.count(kmer) >= kmer_threshold)
+            };
+            let mut start = 0;
+            for run in mins.chunk_by(|a, b| a == b) {
+                let (min, end) = (run[0], start + run.len());
+                if replay_counts.add_and_count(min) < min_threshold
+                    && min_counts.count(min) >= min_threshold
+                    && (solid_flank(start.checked_sub(1)) || solid_flank(Some(end)))
+                {
+                    kmers[start..end]
+                        .iter()
+                        .for_each(|&kmer| kmer_counts.add(kmer));
+                    recounted.fetch_add(run.len(), Ordering::Relaxed);
+                }
+                start = end;
             }
+        });
+        eprintln!(
+            "{} k-mers recounted to compensate the minimizer filter",
+            recounted.into_inner()
+        );
+    }
+    for (name, counts) in [("minimizers", &min_counts), ("k-mers", &kmer_counts)]
+        .into_iter()
+        .filter(|_| solid_filter.is_none())
+    {
+        eprintln!(
+            "~{} distinct {} counted (estimated FPR {:.2e})",
+            counts.approx_len(),
+            name,
+            counts.approx_fpr()
+        );
+        if counts.approx_fpr() > SATURATED_FPR {
+            logs::warn(
+                "saturated_filter",
+                format!("the Bloom filter of {name} is saturated, consider allocating more memory with -m"),
+                json!({ "filter": name, "fpr": counts.approx_fpr() }),
+            );
+        }
+        if let Some(metrics) = &metrics {
+            metrics.set_filter(name, counts.approx_len(), counts.approx_fpr());
         }
+    }
+
+    // Exact counts of all occurrences are compared to the full abundance,
+    // unlike Bloom counts which miss the occurrences before the minimizer is solid.
+    let exact_counts = args.exact_above.map(|above| {
+        let exact_counts = ExactCounter::new_with_seed_and_shard_amount(
+            args.exact_capacity,
+            args.seed + K as u64,
+            shard_amount,
+        );
+        count_exact(
+            open_reads(inputs),
+            threads,
+            &kmer_counts,
+            above,
+            &exact_counts,
+            args.stranded,
+            handling,
+        );
+        (above, exact_counts)
+    });
+    let heavy_kmers: HashSet<_> = args
+        .heavy_hitters
+        .map(|n| {
+            let shard_capacity = n * HEAVY_HITTERS_SLACK;
+            let min_sketch = HeavyHitters::new_with_seed_and_shard_amount(
+                shard_capacity,
+                args.seed + M as u64,
+                shard_amount,
+            );
+            let kmer_sketch = HeavyHitters::new_with_seed_and_shard_amount(
+                shard_capacity,
+                args.seed + K as u64,
+                shard_amount,
+            );
+            open_reads(inputs).process_par(threads as u32, 32, |nucs| {
+                let mut prev_min = None;
+                for_each_kmer_with_minimizer(
+                    handling.resolve(nucs.as_slice()).iter(),
+                    args.seed + W as u64,
+                    args.stranded,
+                    |kmer, min| {
+                        if prev_min != Some(min) {
+                            if min_counts.count(min) >= min_threshold {
+                                min_sketch.add(min);
+                            }
+                            prev_min = Some(min);
+                        }
+                        if kmer_counts.count(kmer) >= kmer_threshold {
+                            kmer_sketch.add(kmer);
+                        }
+                    },
+                );
+            });
+            eprintln!("Most frequent minimizers (count, maximum overestimation):");
+            for (min, count, error) in min_sketch.top(n) {
+                eprintln!(
+                    "{}\t{}\t{}",
+                    String::from_utf8_lossy(&min.to_nucs()),
+                    count,
+                    error
+                );
+            }
+            let top_kmers = kmer_sketch.top(n);
+            eprintln!("Most frequent k-mers (count, maximum overestimation):");
+            for &(kmer, count, error) in top_kmers.iter() {
+                eprintln!(
+                    "{}\t{}\t{}",
+                    String::from_utf8_lossy(&kmer.to_nucs()),
+                    count,
+                    error
+                );
+            }
+            if args.infer_adapters {
+                let coverage = kmer_spectrum(
+                    open_reads(inputs),
+                    threads,
+                    &kmer_counts,
+                    args.stranded,
+                    handling,
+                )
+                .peak(kmer_threshold);
+                let counts: HashMap<_, _> = top_kmers
+                    .iter()
+                    .map(|&(kmer, count, error)| (kmer, count - error))
+                    .collect();
+                let min_count = ADAPTER_COVERAGE_RATIO * coverage as u64;
+                eprintln!("Candidate adapters (coverage peak at {}):", coverage);
+                for adapter in infer_adapters(&counts, min_count, args.stranded) {
+                    eprintln!("{}", String::from_utf8_lossy(&adapter));
+                }
+            }
+            top_kmers.into_iter().map(|(kmer, _, _)| kmer).collect()
+        })
+        .unwrap_or_default();
+    let profile = args
+        .substitution_profile
+        .as_deref()
+        .map(SubstitutionProfile::from_file);
+    let assembler = args.local_assembly.then(|| {
+        let mut assembler = LocalAssembler::new(args.seed, args.stranded);
+        open_reads(inputs).process_par_result(
+            threads as u32,
+            32,
+            |nucs, (read, minimizers): &mut (Vec<u8>, Vec<_>)| {
+                read.clear();
+                read.extend(
+                    handling
+                        .resolve(nucs.as_slice())
+                        .iter()
+                        .filter(|nuc| !is_line_break(nuc)),
+                );
+                assembly::read_minimizers(read, args.seed, args.stranded, minimizers);
+            },
+            |(read, minimizers)| assembler.add(read, minimizers),
+        );
+        eprintln!("{} reads buffered for local assembly", assembler.len());
+        assembler
     });
+    let masked_kmers = args
+        .mask_kmers
+        .as_deref()
+        .map(|filename| read_kmers(filename, args.stranded))
+        .unwrap_or_default();
+    let solid_kmer = |kmer: RawKmer<K, KT>| {
+        let kmer = oriented(kmer, args.stranded);
+        if masked_kmers.contains(&kmer) {
+            return false;
+        }
+        if let Some(solid_filter) = &solid_filter {
+            return solid_filter.contains(kmer);
+        }
+        let count = kmer_counts.count(kmer);
+        match &exact_counts {
+            Some((above, exact_counts)) if count >= *above => exact_counts
+                .count(kmer)
+                .map_or(count >= kmer_threshold, |exact| {
+                    exact >= args.abundance as u32
+                }),
+            _ => count >= kmer_threshold,
+        }
+    };
+    let prefetch_kmer = |kmer: RawKmer<K, KT>| {
+        let kmer = oriented(kmer, args.stranded);
+        match &solid_filter {
+            Some(solid_filter) => solid_filter.prefetch(kmer),
+            None => kmer_counts.prefetch(kmer),
+        }
+    };
 
-    let reads = Fasta::from_file(input_filename);
-    let output = File::create(output_filename).expect("Failed to open output file");
-    let mut writer = BufWriter::new(output);
-    let mut global_stats = Stats::default();
-    reads.process_rec_par_result(
-        threads as u32,
-        32,
-        |record, (buffer, stats): &mut (Vec<u8>, Stats)| {
-            correct(record.seq().iter(), solid_kmer, buffer, stats)
-        },
-        |record, (buffer, stats)| {
-            writer.write_all(b">").unwrap();
-            writer
-                .write_all(record.head())
-                .expect("Failed to write record header");
-            writer.write_all(b"\n").unwrap();
-            writer.write_all(buffer).expect("Failed to write buffer");
-            writer.write_all(b"\n").unwrap();
-            global_stats += *stats;
-        },
+    assert!(
+        args.output_shards >= 1,
+        "The number of output shards must be ≥ 1"
     );
-    println!("{:?}", global_stats);
+    let mut output_filenames = Vec::new();
+    let shard_hasher =
+        RandomState::with_seeds(args.seed, args.seed + 1, args.seed + 2, args.seed + 3);
+    let selection =
+        ReadSelection::new(args.include_names.as_deref(), args.exclude_names.as_deref())
+            .lengths(lengths.clone());
+    let mut mask_writer = args.emit_mask.as_ref().map(|filename| {
+        BufWriter::new(outputs.create(filename).expect("Failed to open mask file"))
+    });
+    let mut events_writer = args.events.as_ref().map(|filename| {
+        BufWriter::new(
+            outputs
+                .create(filename)
+                .expect("Failed to open events file"),
+        )
+    });
+    let mut discordant_writer = args.discordant_pairs.as_ref().map(|filename| {
+        BufWriter::new(
+            outputs
+                .create(filename)
+                .expect("Failed to open discordant pairs file"),
+        )
+    });
+    let mut discordant_pairs = 0;
+    let host_filter = args.host.as_deref().map(|filename| {
+        // Replacing the ambiguous bases of the reference would add k-mers it does not have.
+        let handling = NucHandling {
+            policy: NPolicy::SkipKmers,
+            ..handling
+        };
+        binning::build_filter(filename, default_hashes, args.seed, threads, handling)
+    });
+    let mut host_writer = args.host_output.as_ref().map(|filename| {
+        BufWriter::new(
+            outputs
+                .create(filename)
+                .expect("Failed to open host output file"),
+        )
+    });
+    let counting = start.elapsed();
+    let thread_stats = PerThreadStats::new(threads);
+    for (input_filename, output_filename) in inputs.iter().zip(input_outputs) {
+        let shard_filenames = shard_filenames(&output_filename, args.output_shards);
+        let mut writers: Vec<_> = shard_filenames
+            .iter()
+            .map(|filename| {
+                let writer = if args.quick.is_some() {
+                    Ok(Box::new(io::sink()) as Box<dyn FinishWrite>)
+                } else if args.bgzf {
+                    outputs.create_bgzf(filename)
+                } else {
+                    outputs.create(filename)
+                };
+                BufWriter::new(writer.expect("Failed to open output file"))
+            })
+            .collect();
+        let mut next_shard = 0;
+        let mut shard_bases = vec![0; writers.len()];
+        // Name of the first mate of the current pair with --interleaved.
+        let mut mate: Option<Vec<u8>> = None;
+        // Name, original and corrected sequences of the first mate with --discordant-pairs.
+        let mut first_mate = (Vec::new(), Vec::new(), Vec::new());
+        open_all_reads(std::slice::from_ref(input_filename))
+            .ordered(args.ordered || args.interleaved)
+            .stall_warning(
+                args.max_time_per_read_ms
+                    .map_or(STALL_WARNING, Duration::from_millis),
+            )
+            .process_rec_par_result(
+                threads as u32,
+                32,
+                |record, read: &mut CorrectedRead| {
+                    let start = Instant::now();
+                    read.failure = None;
+                    let outcome = logs::catch_read_panic(|| {
+                        read.dropped = false;
+                        read.host = false;
+                        // The k-mers of every pass are those of the sequence resolved
+                        // like when counting.
+                        let resolved = handling.resolve(record.seq());
+                        if args.emit_mask.is_some() {
+                            solidity_mask(&resolved, solid_kmer, prefetch_kmer, &mut read.mask);
+                        }
+                        if !selection.contains(&record) {
+                            read.pass_through(&record);
+                            read.dropped = args.drop_unselected;
+                            return;
+                        }
+                        if args
+                            .only_if_mean_q_below
+                            .zip(mean_quality(&record))
+                            .is_some_and(|(threshold, quality)| quality >= threshold)
+                        {
+                            read.pass_through(&record);
+                            read.stats.high_quality_reads = 1;
+                            return;
+                        }
+                        if let Some(host_filter) = &host_filter {
+                            let (hits, total) = run_kmers(&resolved).fold(
+                                (0, 0),
+                                |(hits, total), kmer: RawKmer<K, KT>| {
+                                    (
+                                        hits + host_filter.contains(kmer.canonical()) as usize,
+                                        total + 1,
+                                    )
+                                },
+                            );
+                            if total > 0 && hits as f64 >= args.host_fraction * total as f64 {
+                                read.pass_through(&record);
+                                read.stats.host_reads = 1;
+                                read.host = true;
+                                return;
+                            }
+                        }
+                        if let Some(max_weak_fraction) = args.prescreen {
+                            if sampled_weak_fraction(&resolved, solid_kmer, W) <= max_weak_fraction
+                            {
+                                read.pass_through(&record);
+                                read.stats.prescreened_reads = 1;
+                                return;
+                            }
+                        }
+                        if let Some(repeat_fraction) = args.repeat_fraction {
+                            let dominant =
+                                dominant_minimizer(&resolved, args.seed + W as u64, args.stranded);
+                            if dominant.is_some_and(|(min, run, kmers)| {
+                                run as f64 >= repeat_fraction * kmers as f64
+                                    && min_counts.count(min) >= min_threshold
+                            }) {
+                                read.pass_through(&record);
+                                read.stats.repeat_reads = 1;
+                                return;
+                            }
+                        }
+                        let coverage = if args.protect_het || args.min_relative_abundance.is_some()
+                        {
+                            median_abundance(&resolved, &kmer_counts, kmer_threshold, args.stranded)
+                                as f64
+                        } else {
+                            0.0
+                        };
+                        let relative_threshold = args
+                            .min_relative_abundance
+                            .filter(|_| coverage > 0.0)
+                            .map(|fraction| (fraction * coverage).ceil().max(1.0));
+                        // K-mers close to the read ends are spanned by fewer reads,
+                        // so their threshold ramps up from a fraction of the solid threshold.
+                        let end_thresholds: HashMap<_, _> = args
+                            .end_ramp
+                            .map(|ramp| {
+                                let kmers: Vec<RawKmer<K, KT>> = run_kmers(&resolved).collect();
+                                let n = kmers.len();
+                                kmers
+                                    .into_iter()
+                                    .enumerate()
+                                    .filter_map(|(i, kmer)| {
+                                        let dist = i.min(n - 1 - i);
+                                        let fraction = args.end_ramp_min
+                                            + (1.0 - args.end_ramp_min) * dist as f64 / ramp as f64;
+                                        (dist < ramp).then(|| {
+                                            (
+                                                kmer,
+                                                (fraction * kmer_threshold as f64).ceil().max(1.0),
+                                            )
+                                        })
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        let solid_read = |kmer: RawKmer<K, KT>| {
+                            if solid_kmer(kmer) {
+                                return true;
+                            }
+                            let threshold = relative_threshold
+                                .into_iter()
+                                .chain(end_thresholds.get(&kmer).copied())
+                                .reduce(f64::min);
+                            let kmer = oriented(kmer, args.stranded);
+                            threshold.is_some_and(|threshold| {
+                                !masked_kmers.contains(&kmer)
+                                    && kmer_counts.count(kmer) as f64 >= threshold
+                            })
+                        };
+                        let half_coverage = |kmer: RawKmer<K, KT>| {
+                            let ratio =
+                                kmer_counts.count(oriented(kmer, args.stranded)) as f64 / coverage;
+                            (HET_RATIO_MIN..=HET_RATIO_MAX).contains(&ratio)
+                        };
+                        let heterozygous = |left, right| {
+                            args.protect_het
+                                && coverage > 0.0
+                                && half_coverage(left)
+                                && half_coverage(right)
+                        };
+                        let anchor = |kmer: RawKmer<K, KT>| {
+                            let kmer = oriented(kmer, args.stranded);
+                            !heavy_kmers.contains(&kmer)
+                                && args
+                                    .max_anchor_abundance
+                                    .is_none_or(|max| kmer_counts.count(kmer) < max)
+                        };
+                        let deadline = args
+                            .max_time_per_read_ms
+                            .map(|ms| Instant::now() + Duration::from_millis(ms));
+                        correct(
+                            record.seq().iter(),
+                            record.opt_qual(),
+                            handling,
+                            solid_read,
+                            prefetch_kmer,
+                            heterozygous,
+                            anchor,
+                            deadline,
+                            profile.as_ref(),
+                            assembler.as_ref(),
+                            &mut read.seq,
+                            &mut read.qual,
+                            &mut read.parts,
+                            &mut read.stats,
+                        );
+                        if let Some(max_changed_fraction) = args.max_changed_fraction {
+                            let original = comparable_bases(record.seq(), args.n_policy);
+                            let changed = changed_bases(&original, &read.seq);
+                            if changed as f64 > max_changed_fraction * original.len() as f64 {
+                                read.pass_through(&record);
+                                read.stats.guarded_reads = 1;
+                                return;
+                            }
+                        }
+                        if args.detect_only {
+                            let stats = read.stats;
+                            read.pass_through(&record);
+                            read.stats = stats;
+                        }
+                    });
+                    if let Err(payload) = outcome {
+                        read.pass_through(&record);
+                        read.dropped = false;
+                        read.failure = Some(panic_message(payload.as_ref()));
+                        read.stats.failed_reads = 1;
+                    }
+                    read.event.clear();
+                    if args.events.is_some() && read.seq[..] != record.full_seq()[..] {
+                        // Removing the ambiguous bases of a split read alone does not modify it.
+                        let original = comparable_bases(record.seq(), args.n_policy);
+                        if read.seq != original {
+                            let kmers: Vec<_> = run_kmers(&handling.resolve(&read.seq)).collect();
+                            let solid = kmers.iter().filter(|&&kmer| solid_kmer(kmer)).count();
+                            read.event = events::read_event(
+                                record.id_bytes(),
+                                &original,
+                                &read.seq,
+                                &read.stats,
+                                solid as f64 / kmers.len().max(1) as f64,
+                                start.elapsed(),
+                            );
+                        }
+                    }
+                    thread_stats.add(&read.stats, start.elapsed());
+                    if let Some(metrics) = &metrics {
+                        metrics.add_read(&read.stats);
+                    }
+                },
+                |record, read| {
+                    if let Some(metrics) = &metrics {
+                        metrics.add_written();
+                    }
+                    if let Some(writer) = mask_writer.as_mut() {
+                        writer.write_all(b">").unwrap();
+                        writer
+                            .write_all(record.head())
+                            .expect("Failed to write mask header");
+                        writer.write_all(b"\n").unwrap();
+                        writer.write_all(&read.mask).expect("Failed to write mask");
+                        writer.write_all(b"\n").unwrap();
+                    }
+                    if let Some(writer) = events_writer.as_mut().filter(|_| !read.event.is_empty())
+                    {
+                        writeln!(writer, "{}", read.event).expect("Failed to write event");
+                    }
+                    if let Some(reason) = &read.failure {
+                        logs::read_error(
+                            "failed_read",
+                            record.id_bytes(),
+                            format!("failed to correct, passed through unmodified: {reason}"),
+                        );
+                    }
+                    if args.interleaved {
+                        let name = pair_name(record.id_bytes());
+                        match mate.take() {
+                            None => mate = Some(name.to_vec()),
+                            Some(first) => assert!(
+                            first == name,
+                            "Read {} is not the mate of the previous read in the interleaved input",
+                            String::from_utf8_lossy(record.id_bytes())
+                        ),
+                        }
+                    }
+                    if let Some(writer) = discordant_writer.as_mut() {
+                        if mate.is_some() {
+                            first_mate = (
+                                record.id_bytes().to_vec(),
+                                record.full_seq().into_owned(),
+                                read.seq.clone(),
+                            );
+                        } else {
+                            let offset = |mate1: &[u8], mate2: &[u8]| {
+                                pairs::mate_offset(mate1, mate2, |kmer: RawKmer<K, KT>| {
+                                    solid_kmer(kmer)
+                                })
+                            };
+                            let before = offset(&first_mate.1, &record.full_seq());
+                            let after = offset(&first_mate.2, &read.seq);
+                            if pairs::diverged(before, after) {
+                                discordant_pairs += 1;
+                                for id in [&first_mate.0[..], record.id_bytes()] {
+                                    writer
+                                        .write_all(id)
+                                        .and_then(|_| writer.write_all(b"\n"))
+                                        .expect("Failed to write discordant pair");
+                                }
+                            }
+                        }
+                    }
+                    if read.dropped {
+                        return;
+                    }
+                    if let Some(writer) = host_writer.as_mut().filter(|_| read.host) {
+                        write_read(writer, &record, &read.seq, &read.qual)
+                            .expect("Failed to write host read");
+                        return;
+                    }
+                    let shard = match args.shard_by {
+                        ShardBy::RoundRobin => {
+                            let shard = next_shard;
+                            // Mates go to the same shard.
+                            if mate.is_none() {
+                                next_shard = (next_shard + 1) % writers.len();
+                            }
+                            shard
+                        }
+                        ShardBy::Name => {
+                            shard_hasher.hash_one(pair_name(record.id_bytes())) as usize
+                                % writers.len()
+                        }
+                        ShardBy::Size => {
+                            // The second mate follows the first one.
+                            if mate.is_some() || !args.interleaved {
+                                next_shard = smallest_shard(&shard_bases);
+                            }
+                            shard_bases[next_shard] += read.seq.len();
+                            next_shard
+                        }
+                    };
+                    write_parts(
+                        &mut writers[shard],
+                        &record,
+                        &read.seq,
+                        &read.qual,
+                        &read.parts,
+                    )
+                    .expect("Failed to write read");
+                },
+            );
+        assert!(
+            mate.is_none(),
+            "The interleaved input {input_filename} has an odd number of reads"
+        );
+        writers
+            .iter_mut()
+            .for_each(|writer| writer.finish().expect("Failed to finish output"));
+        output_filenames.extend(shard_filenames);
+    }
+    let per_thread = thread_stats.per_thread();
+    if args.verbose {
+        for (i, thread) in per_thread.iter().enumerate() {
+            eprintln!("Thread {i}: {} reads in {:.2?}", thread.reads, thread.busy);
+        }
+    }
+    let stats = merge_threads(&per_thread);
+    if to_stdout {
+        eprintln!("{stats:?}");
+    } else {
+        println!("{stats:?}");
+    }
+    if args.discordant_pairs.is_some() {
+        eprintln!("{discordant_pairs} pairs diverged after correction");
+    }
+    if let Some(fraction) = args.quick {
+        let reads: usize = per_thread.iter().map(|thread| thread.reads).sum();
+        let extrapolate = |count: usize| (count as f64 / fraction).round() as u64;
+        eprintln!(
+            "Quick look at {:.2}% of the reads, extrapolated to all of them: \
+             ~{} reads, ~{} weak regions ({:.3} per read), ~{} corrections ({:.3} per read), \
+             ~{:.0?} for a full run",
+            fraction * 100.0,
+            extrapolate(reads),
+            extrapolate(stats.errors),
+            stats.errors as f64 / reads.max(1) as f64,
+            extrapolate(stats.corrections),
+            stats.corrections as f64 / reads.max(1) as f64,
+            counting + (start.elapsed() - counting).div_f64(fraction),
+        );
+    }
+
+    if let Some(spectrum_filename) = args.spectrum {
+        let before = kmer_spectrum(
+            open_reads(inputs),
+            threads,
+            &kmer_counts,
+            args.stranded,
+            handling,
+        );
+        drop(min_counts);
+        drop(kmer_counts);
+        let min_counts = new_filter(min_size, min_hashes, args.seed + M as u64);
+        let kmer_counts = new_filter(kmer_size, kmer_hashes, args.seed + K as u64);
+        let output_paths: Vec<_> = output_filenames
+            .iter()
+            .map(|filename| outputs.path(filename))
+            .collect();
+        let after = if alignment_format(&inputs[0]).is_some() {
+            // The reads of alignment files are written as FASTQ.
+            recount_spectrum(
+                || {
+                    let reads =
+                        Fastq::from_files_with_batch_size(&output_paths, args.batch_size << 10);
+                    Subsample::new(reads, counted_fraction, args.seed).lengths(lengths.clone())
+                },
+                threads,
+                &min_counts,
+                &kmer_counts,
+                min_threshold,
+                args.seed,
+                args.stranded,
+                handling,
+            )
+        } else {
+            recount_spectrum(
+                || open_reads(&output_paths),
+                threads,
+                &min_counts,
+                &kmer_counts,
+                min_threshold,
+                args.seed,
+                args.stranded,
+                handling,
+            )
+        };
+        let output = outputs
+            .create(&spectrum_filename)
+            .expect("Failed to open spectrum file");
+        let mut writer = BufWriter::new(output);
+        Spectrum::write_comparison(&before, &after, &mut writer)
+            .and_then(|_| writer.finish())
+            .expect("Failed to write spectrum");
+    }
+
+    for writer in mask_writer
+        .iter_mut()
+        .chain(host_writer.iter_mut())
+        .chain(events_writer.iter_mut())
+        .chain(discordant_writer.iter_mut())
+    {
+        writer.finish().expect("Failed to finish output");
+    }
+    drop(mask_writer);
+    drop(host_writer);
+    drop(events_writer);
+    drop(discordant_writer);
+    let filenames = outputs.commit().expect("Failed to rename output files");
+    if let Some(done_filename) = args.done_file {
+        write_done_file(&done_filename, &filenames).expect("Failed to write done file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use testing::random_nucs;
+
+    /// Random sequence of `len` bases with an N in its middle, along with the k-mers spanning the N
+    /// that joining the bases around it would produce.
+    fn read_with_n(len: usize) -> (Vec<u8>, Vec<RawKmer<K, KT>>) {
+        let mut read = random_nucs(len, 42);
+        read[len / 2] = b'N';
+        let joined: Vec<u8> = read.iter().copied().filter(|&nuc| nuc != b'N').collect();
+        let spanning = RawKmer::<K, KT>::iter_from_nucs(joined.iter())
+            .enumerate()
+            .filter(|&(i, _)| i + K > len / 2 && i < len / 2)
+            .map(|(_, kmer)| kmer)
+            .collect();
+        (read, spanning)
+    }
+
+    #[test]
+    fn test_count_exact_skips_ambiguous() {
+        let (read, spanning) = read_with_n(3 * K);
+        let fasta = [b">r\n".as_slice(), &read, b"\n"].concat();
+        let kmer_counts = CountingBloomFilter::new_with_seed(1 << 16, 2, 42);
+        let exact_counts = ExactCounter::new_with_seed_and_shard_amount(1 << 10, 42, 1);
+        for policy in [NPolicy::SkipKmers, NPolicy::SplitRead] {
+            count_exact(
+                Fasta::from_reader_with_batch_size(Cursor::new(fasta.clone()), 1 << 10),
+                1,
+                &kmer_counts,
+                0,
+                &exact_counts,
+                false,
+                NucHandling::new(policy),
+            );
+        }
+        assert!(spanning
+            .iter()
+            .all(|&kmer| exact_counts.count(oriented(kmer, false)).is_none()));
+        for kmer in run_kmers(&read) {
+            assert_eq!(exact_counts.count(oriented(kmer, false)), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_count_exact_resolves_softmask_and_iupac() {
+        let (mut read, _) = read_with_n(3 * K);
+        read[3 * K / 2] = b'R';
+        read[..K].make_ascii_lowercase();
+        let fasta = [b">r\n".as_slice(), &read, b"\n"].concat();
+        let kmer_counts = CountingBloomFilter::new_with_seed(1 << 16, 2, 42);
+        let exact_counts = ExactCounter::new_with_seed_and_shard_amount(1 << 10, 42, 1);
+        let handling = NucHandling {
+            canonicalize_iupac: true,
+            respect_softmask: true,
+            ..NucHandling::default()
+        };
+        count_exact(
+            Fasta::from_reader_with_batch_size(Cursor::new(fasta), 1 << 10),
+            1,
+            &kmer_counts,
+            0,
+            &exact_counts,
+            false,
+            handling,
+        );
+        // Every k-mer is counted, including those overlapping the lowercase bases and the R.
+        let kmers: Vec<_> = run_kmers(&handling.resolve(&read)).collect();
+        assert_eq!(kmers.len(), 2 * K + 1);
+        for kmer in kmers {
+            assert_eq!(exact_counts.count(oriented(kmer, false)), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_solidity_mask_skips_ambiguous() {
+        let len = 3 * K;
+        let (read, spanning) = read_with_n(len);
+        let weak: HashSet<_> = spanning.into_iter().collect();
+        let mut mask = Vec::new();
+        solidity_mask(&read, |kmer| !weak.contains(&kmer), |_| (), &mut mask);
+        // Only the k-mers on each side of the N are reported, all of them solid.
+        let (left, right) = (len / 2, len - len / 2 - 1);
+        assert_eq!(mask, vec![b'S'; (left + 1 - K) + (right + 1 - K)]);
+    }
+
+    #[test]
+    fn test_kmer_minimizer_state_reset() {
+        let seq = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGTCCGATTACAGGT";
+        let scan = |state: &mut KmerMinimizerState| -> Vec<_> {
+            seq.iter()
+                .filter_map(MT::from_nuc)
+                .filter_map(|base| state.push(base))
+                .collect()
+        };
+        let mut state = KmerMinimizerState::new(42, false);
+        let first = scan(&mut state);
+        assert_eq!(first.len(), seq.len() - K + 1);
+        state.reset();
+        assert_eq!(scan(&mut state), first);
+    }
+
+    #[test]
+    fn test_kmer_minimizer_iter() {
+        let seq = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGTCCGATTACAGGTNNACGTTGCAAGGCTAGC";
+        let items: Vec<_> = KmerMinimizerIter::new(seq.iter(), 42, false).collect();
+        // The k-mers overlapping the Ns are skipped.
+        let expected: usize = [52usize, 18]
+            .map(|len| (len + 1).saturating_sub(K))
+            .iter()
+            .sum();
+        assert_eq!(items.len(), expected);
+        let mut kmers = Vec::new();
+        for_each_kmer_with_minimizer(seq.iter(), 42, false, |kmer, min| kmers.push((kmer, min)));
+        assert_eq!(items.len(), kmers.len());
+        assert!(items[0].2);
+        for (i, &(kmer, min, changed)) in items.iter().enumerate() {
+            assert_eq!((kmer, min), kmers[i]);
+            assert_eq!(kmer, kmer.canonical());
+            assert_eq!(changed, i == 0 || items[i - 1].1 != min);
+        }
+    }
+
+    #[test]
+    fn test_dominant_minimizer_kmers() {
+        let seq = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGTCCGATTACAGGT\nNNACGTTGCAAGGCTAGC";
+        let (_, run, kmers) = dominant_minimizer(seq, 42, false).unwrap();
+        // Line breaks and ambiguous bases are not counted as k-mers.
+        assert_eq!(kmers, 52 + 1 - K);
+        assert!(run <= kmers);
+        assert!(dominant_minimizer(b"ACGTN", 42, false).is_none());
+    }
+
+    #[test]
+    fn test_count_read_kmers() {
+        let seq = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGTCCGATTACAGGT";
+        let kmers: Vec<_> = RawKmer::<K, KT>::iter_from_nucs(seq.iter())
+            .map(|kmer| kmer.canonical())
+            .collect();
+        let counts = |min_threshold| {
+            let min_counts = CountingBloomFilter::new_with_seed(1 << 16, 2, 42);
+            let kmer_counts = CountingBloomFilter::new_with_seed(1 << 16, 2, 43);
+            count_read_kmers(
+                seq.iter(),
+                &min_counts,
+                &kmer_counts,
+                min_threshold,
+                42,
+                false,
+            );
+            kmers
+                .iter()
+                .map(|&kmer| kmer_counts.count(kmer))
+                .collect::<Vec<_>>()
+        };
+        assert!(counts(1).iter().all(|&count| count == 1));
+        assert!(counts(u8::MAX).iter().all(|&count| count == 0));
+    }
+
+    #[test]
+    fn test_tagged_filename() {
+        assert_eq!(tagged_filename("reads.fa", "cor"), "reads.cor.fa");
+        assert_eq!(tagged_filename("reads", 0), "reads.0");
+        assert_eq!(tagged_filename("data.v2/reads", "cor"), "data.v2/reads.cor");
+        assert_eq!(
+            tagged_filename("data.v2/reads.fa", "cor"),
+            "data.v2/reads.cor.fa"
+        );
+        assert_eq!(tagged_filename("reads.fq.gz", "cor"), "reads.cor.fq");
+        assert_eq!(tagged_filename("reads.fa.zst", "cor"), "reads.cor.fa");
+        assert_eq!(tagged_output_filename("out.fq.gz", 0), "out.0.fq.gz");
+        assert_eq!(tagged_output_filename("out.fq", 0), "out.0.fq");
+    }
+
+    #[test]
+    fn test_templated_filename() {
+        let template = "{stem}.corrected.{ext}";
+        assert_eq!(
+            templated_filename("data/reads.fq.gz", template),
+            "data/reads.corrected.fq"
+        );
+        assert_eq!(templated_filename("reads", template), "reads.corrected");
+        assert_eq!(
+            templated_filename("reads.fa", "cor_{stem}.fasta"),
+            "cor_reads.fasta"
+        );
+    }
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(parse_fraction("1%"), Ok(0.01));
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert!(parse_fraction("0").is_err());
+        assert!(parse_fraction("150%").is_err());
+        assert!(parse_fraction("one").is_err());
+    }
+
+    #[test]
+    fn test_smallest_shard() {
+        assert_eq!(smallest_shard(&[3, 1, 2, 1]), 1);
+        assert_eq!(smallest_shard(&[0, 0]), 0);
+    }
+
+    #[test]
+    fn test_write_read() {
+        let mut reader = seq_io::fastq::Reader::new(&b"@r0 desc\nACGT\n+\nIIII\n"[..]);
+        let record = reader.next().unwrap().unwrap();
+        let mut out = Vec::new();
+        write_read(&mut out, &record, b"ACGT", b"IIII").unwrap();
+        write_read(&mut out, &record, b"ACGTA", b"IIII").unwrap();
+        write_read(&mut out, &record, b"ACG", b"IIII").unwrap();
+        write_read(&mut out, &record, b"ACGTA", b"II#II").unwrap();
+        assert_eq!(
+            out,
+            b"@r0 desc\nACGT\n+\nIIII\n@r0 desc\nACGTA\n+\nIIII!\n@r0 desc\nACG\n+\nIII\n@r0 desc\nACGTA\n+\nII#II\n"
+        );
+    }
+
+    #[test]
+    fn test_write_read_comments() {
+        let input =
+            b"@r0 desc\nACGT\n+r0 desc\nIIII\n@r1\r\nACGT\r\n+ note\r\nIIII\r\n@r2\n\n+c\n\n";
+        let mut reader = seq_io::fastq::Reader::new(&input[..]);
+        let mut out = Vec::new();
+        while let Some(record) = reader.next() {
+            let record = record.unwrap();
+            write_read(
+                &mut out,
+                &record,
+                &record.full_seq(),
+                &record.opt_full_qual().unwrap(),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            out,
+            b"@r0 desc\nACGT\n+r0 desc\nIIII\n@r1\nACGT\n+ note\nIIII\n@r2\n\n+c\n\n"
+        );
+        let mut reader = seq_io::fasta::Reader::new(&b">r0 desc text\nAC\nGT\n"[..]);
+        let record = reader.next().unwrap().unwrap();
+        let mut out = Vec::new();
+        write_read(&mut out, &record, b"ACGA", b"").unwrap();
+        assert_eq!(out, b">r0 desc text\nACGA\n");
+    }
 }
@@ -0,0 +1,143 @@
+use crate::correction::Stats;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Live counters of a run, exposed in the Prometheus text format.
+///
+/// Unlike the per-thread stats, which are only merged at the end of the run,
+/// these counters are updated as reads are processed so that they can be scraped.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    reads: AtomicUsize,
+    written: AtomicUsize,
+    errors: AtomicUsize,
+    corrections: AtomicUsize,
+    failed_reads: AtomicUsize,
+    filters: Mutex<Vec<(&'static str, usize, f64)>>,
+}
+
+impl Metrics {
+    pub fn add_read(&self, stats: &Stats) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(stats.errors, Ordering::Relaxed);
+        self.corrections
+            .fetch_add(stats.corrections, Ordering::Relaxed);
+        self.failed_reads
+            .fetch_add(stats.failed_reads, Ordering::Relaxed);
+    }
+
+    /// Records that a processed read left the output queue.
+    pub fn add_written(&self) {
+        self.written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the occupancy of a counting filter once it is filled.
+    pub fn set_filter(&self, name: &'static str, distinct: usize, fpr: f64) {
+        self.filters.lock().unwrap().push((name, distinct, fpr));
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (metric, help, value) in [
+            ("brrr_reads_total", "Reads processed", &self.reads),
+            (
+                "brrr_errors_total",
+                "Weak regions for which a correction was attempted",
+                &self.errors,
+            ),
+            (
+                "brrr_corrections_total",
+                "Weak regions that were corrected",
+                &self.corrections,
+            ),
+            (
+                "brrr_failed_reads_total",
+                "Reads whose correction failed",
+                &self.failed_reads,
+            ),
+        ] {
+            writeln!(out, "# HELP {metric} {help}").unwrap();
+            writeln!(out, "# TYPE {metric} counter").unwrap();
+            writeln!(out, "{metric} {}", value.load(Ordering::Relaxed)).unwrap();
+        }
+        // Reads are counted when their processing ends, so the queue holds the
+        // processed reads waiting for their turn to be written.
+        let queued = self
+            .reads
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.written.load(Ordering::Relaxed));
+        writeln!(
+            out,
+            "# HELP brrr_queue_depth Processed reads waiting to be written"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE brrr_queue_depth gauge").unwrap();
+        writeln!(out, "brrr_queue_depth {queued}").unwrap();
+        let filters = self.filters.lock().unwrap();
+        writeln!(
+            out,
+            "# HELP brrr_filter_distinct Approximate number of distinct items in a filter"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE brrr_filter_distinct gauge").unwrap();
+        for (name, distinct, _) in filters.iter() {
+            writeln!(out, "brrr_filter_distinct{{filter=\"{name}\"}} {distinct}").unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP brrr_filter_fpr Estimated false positive rate of a filter"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE brrr_filter_fpr gauge").unwrap();
+        for (name, _, fpr) in filters.iter() {
+            writeln!(out, "brrr_filter_fpr{{filter=\"{name}\"}} {fpr}").unwrap();
+        }
+        out
+    }
+}
+
+/// Serves `metrics` over HTTP on `addr:port` from a background thread, which answers
+/// every request with the current metrics until the process exits.
+pub fn serve(addr: IpAddr, port: u16, metrics: Arc<Metrics>) {
+    let listener = TcpListener::bind((addr, port)).expect("Failed to bind metrics port");
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let body = metrics.render();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.add_read(&Stats {
+            errors: 3,
+            corrections: 2,
+            ..Stats::default()
+        });
+        metrics.add_read(&Stats::default());
+        metrics.add_written();
+        metrics.set_filter("k-mers", 42, 0.01);
+        let out = metrics.render();
+        assert!(out.contains("brrr_reads_total 2\n"));
+        assert!(out.contains("brrr_errors_total 3\n"));
+        assert!(out.contains("brrr_corrections_total 2\n"));
+        assert!(out.contains("brrr_queue_depth 1\n"));
+        assert!(out.contains("brrr_filter_distinct{filter=\"k-mers\"} 42\n"));
+    }
+}
@@ -21,6 +21,12 @@ impl<const W: usize, T: Hash + Copy> MinimizerQueue<W, T> {
         Self::new_with_seed(W as u64)
     }
 
+    /// Empties the queue to start a new sequence, keeping its allocation and its hash function.
+    pub fn clear(&mut self) {
+        self.deq.clear();
+        self.pos = 0;
+    }
+
     pub fn get_min(&self) -> T {
         debug_assert!(!self.deq.is_empty(), "MinimizerQueue is empty");
         self.deq[0].0
@@ -70,6 +76,19 @@ mod tests {
         assert_ne!(queue.get_min(), RawKmer::<M, T>::from_nucs(b"AAA"));
     }
 
+    #[test]
+    fn test_clear() {
+        let mut queue = MinimizerQueue::<W, _>::new();
+        let mut fresh = MinimizerQueue::<W, _>::new();
+        RawKmer::<M, T>::iter_from_nucs(b"TTTGCA".iter()).for_each(|mmer| queue.insert(mmer));
+        queue.clear();
+        RawKmer::<M, T>::iter_from_nucs(b"AAATAGT".iter()).for_each(|mmer| {
+            queue.insert(mmer);
+            fresh.insert(mmer);
+            assert_eq!(queue.get_min(), fresh.get_min());
+        });
+    }
+
     #[test]
     fn test_hash() {
         let queue = MinimizerQueue::<W, _>::new();
@@ -36,11 +36,11 @@ pub struct InsertionIterator<T: Copy, I: Iterator<Item = T>> {
 impl<T: Copy, I: Iterator<Item = T>> Iterator for InsertionIterator<T, I> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.count != 0 {
-            self.count = self.count.saturating_sub(1);
-            self.iter.next()
-        } else {
+        self.count -= 1;
+        if self.count == -1 {
             Some(self.element)
+        } else {
+            self.iter.next()
         }
     }
 }
@@ -53,13 +53,11 @@ pub struct DeletionIterator<T: Copy, I: Iterator<Item = T>> {
 impl<T: Copy, I: Iterator<Item = T>> Iterator for DeletionIterator<T, I> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.count != 0 {
-            self.count = self.count.saturating_sub(1);
-            self.iter.next()
-        } else {
+        self.count -= 1;
+        if self.count == -1 {
             self.iter.next();
-            self.iter.next()
         }
+        self.iter.next()
     }
 }
 
@@ -72,12 +70,28 @@ pub struct SubstitutionIterator<T: Copy, I: Iterator<Item = T>> {
 impl<T: Copy, I: Iterator<Item = T>> Iterator for SubstitutionIterator<T, I> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.count != 0 {
-            self.count = self.count.saturating_sub(1);
-            self.iter.next()
+        self.count -= 1;
+        let item = self.iter.next();
+        if self.count == -1 {
+            item.map(|_| self.element)
         } else {
-            self.iter.next();
-            Some(self.element)
+            item
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutations() {
+        let v = [0, 1, 2, 3];
+        let mutated = |iter: &mut dyn Iterator<Item = i32>| iter.collect::<Vec<_>>();
+        assert_eq!(mutated(&mut v.into_iter().insertion(1, 9)), [0, 9, 1, 2, 3]);
+        assert_eq!(mutated(&mut v.into_iter().insertion(4, 9)), [0, 1, 2, 3, 9]);
+        assert_eq!(mutated(&mut v.into_iter().deletion(1)), [0, 2, 3]);
+        assert_eq!(mutated(&mut v.into_iter().substitution(1, 9)), [0, 9, 2, 3]);
+        assert_eq!(mutated(&mut v.into_iter().substitution(4, 9)), [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::ambiguous::NucHandling;
+use crate::bam::{alignment_format, fastq_filename};
+use crate::constants::{K, KT};
+use crate::correction::run_kmers;
+use crate::dashbloom::CountingBloomFilter;
+use crate::kmer::{Kmer, RawKmer};
+use crate::output::{FinishWrite, Outputs};
+use crate::reads::{
+    content_size, is_fastq, Bam, BaseRecord, Fasta, Fastq, ReadProcess, Truncations,
+};
+use crate::stats::{merge_threads, Merge, PerThreadStats};
+use crate::{tagged_filename, thread_count, write_read};
+use clap::ValueHint;
+use std::io::BufWriter;
+use std::time::Instant;
+
+#[derive(clap::Args, Debug)]
+pub struct NormalizeArgs {
+    /// Input file (.fasta, .fa, .fastq, .fq, .bam, .sam, optionally compressed)
+    #[arg(value_hint = ValueHint::FilePath)]
+    input: String,
+    /// Output file (defaults to <input>.norm.<ext>)
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Number of threads (defaults to all available threads)
+    #[arg(short, long)]
+    threads: Option<usize>,
+    /// Memory (in MB) allocated to the Bloom filter (defaults to the decompressed input size)
+    #[arg(short, long)]
+    memory: Option<usize>,
+    /// Median k-mer abundance above which reads are dropped
+    #[arg(short = 'c', long, default_value_t = 20)]
+    target_coverage: u8,
+    /// Number of hashes used in Bloom filters
+    #[arg(short = 'H', long, default_value_t = 3)]
+    hashes: usize,
+    /// Seed used for hash functions
+    #[arg(short, long, default_value_t = 101010)]
+    seed: u64,
+    /// Overwrite the existing outputs instead of refusing to start
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NormalizeStats {
+    pub kept: usize,
+    pub dropped: usize,
+}
+
+impl Merge for NormalizeStats {
+    fn merge(&mut self, other: &Self) {
+        self.kept += other.kept;
+        self.dropped += other.dropped;
+    }
+}
+
+/// Digital normalization: streams the reads and drops the ones whose median
+/// k-mer abundance, among the reads kept so far, already reaches the target.
+pub fn normalize(args: NormalizeArgs, truncations: &Truncations) {
+    let input = args.input.clone();
+    if alignment_format(&input).is_some() {
+        normalize_with(args, Bam::from_file(&input).truncations(truncations))
+    } else if is_fastq(&input) {
+        normalize_with(args, Fastq::from_file(&input).truncations(truncations))
+    } else {
+        normalize_with(args, Fasta::from_file(&input))
+    }
+}
+
+/// Normalizes the `reads` of the input, writing each of them in the format of its record.
+fn normalize_with<R: ReadProcess>(args: NormalizeArgs, reads: R) {
+    let threads = thread_count(args.threads);
+    let output_filename = args.output.unwrap_or_else(|| {
+        let name = tagged_filename(&args.input, "norm");
+        // Alignment inputs are written as FASTQ.
+        if alignment_format(&args.input).is_some() {
+            fastq_filename(&name)
+        } else {
+            name
+        }
+    });
+    let size = if let Some(m) = args.memory {
+        m * 1_000_000
+    } else {
+        content_size(&args.input)
+    };
+    let counts = CountingBloomFilter::new_with_seed_and_shard_amount(
+        size,
+        args.hashes,
+        args.seed + K as u64,
+        threads * 4,
+    );
+
+    let mut outputs = Outputs::new(true).threads(threads).overwrite(args.force);
+    let output = outputs
+        .create(&output_filename)
+        .expect("Failed to open output file");
+    let mut writer = BufWriter::new(output);
+    let thread_stats = PerThreadStats::new(threads);
+    let handling = NucHandling::default();
+    reads.process_rec_par_result(
+        threads as u32,
+        32,
+        |record, keep: &mut bool| {
+            let start = Instant::now();
+            let kmers: Vec<_> = run_kmers(&handling.resolve(record.seq()))
+                .map(|kmer: RawKmer<K, KT>| kmer.canonical())
+                .collect();
+            let mut abundances: Vec<_> = kmers.iter().map(|&kmer| counts.count(kmer)).collect();
+            let mid = abundances.len() / 2;
+            *keep = abundances.is_empty()
+                || *abundances.select_nth_unstable(mid).1 < args.target_coverage;
+            if *keep {
+                kmers.iter().for_each(|&kmer| counts.add(kmer));
+            }
+            let stats = NormalizeStats {
+                kept: *keep as usize,
+                dropped: !*keep as usize,
+            };
+            thread_stats.add(&stats, start.elapsed());
+        },
+        |record, keep| {
+            if *keep {
+                let qual = record.opt_full_qual().unwrap_or_default();
+                write_read(&mut writer, &record, &record.full_seq(), &qual)
+                    .expect("Failed to write record");
+            }
+        },
+    );
+    writer.finish().expect("Failed to finish output");
+    drop(writer);
+    outputs.commit().expect("Failed to rename output file");
+    println!("{:?}", merge_threads(&thread_stats.per_thread()));
+}
@@ -0,0 +1,711 @@
+use crate::reads::STDIO;
+use bzip2::write::BzEncoder;
+use clap::ValueEnum;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+use liblzma::write::XzEncoder;
+use niffler::send::compression::Format;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs::{rename, File};
+use std::io::{copy, stdout, BufWriter, Error, ErrorKind, Result, Sink, Stdout, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Default compression level of the outputs.
+const COMPRESSION_LEVEL: u32 = 6;
+
+/// Codec compressing the outputs.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Codec {
+    /// Extension of the files compressed with this codec.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Bzip2 => "bz2",
+            Self::Xz => "xz",
+            Self::Zstd => "zst",
+        }
+    }
+}
+
+/// Extensions of the compression formats supported for outputs.
+pub const COMPRESSION_EXTENSIONS: [&str; 4] = ["gz", "bz2", "xz", "zst"];
+
+/// Returns the compression format of an output file from its extension.
+fn output_format(filename: &str) -> Format {
+    match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Format::Gzip,
+        Some("bz2") => Format::Bzip,
+        Some("xz") => Format::Lzma,
+        Some("zst") => Format::Zstd,
+        _ => Format::No,
+    }
+}
+
+/// Returns the compression levels supported by a format.
+fn levels(format: Format) -> RangeInclusive<u32> {
+    match format {
+        Format::Bzip => 1..=9,
+        Format::Zstd => 1..=22,
+        _ => 0..=9,
+    }
+}
+
+/// Writer of an output, whose stream is only complete once finished, e.g. with the end-of-file
+/// marker of BGZF, so that an error completing it is returned instead of being lost on drop.
+pub trait FinishWrite: Write + Send {
+    /// Writes the remaining data and completes the stream.
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl FinishWrite for File {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl FinishWrite for Stdout {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl FinishWrite for Sink {
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: FinishWrite + ?Sized> FinishWrite for Box<W> {
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for BufWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for BzEncoder<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.try_finish()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for XzEncoder<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.try_finish()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for zstd::Encoder<'static, W> {
+    fn finish(&mut self) -> Result<()> {
+        self.do_finish()?;
+        self.get_mut().finish()
+    }
+}
+
+/// Wraps `file` in an encoder compressing on the fly at `level` if `filename` ends in a compression
+/// extension, gzip being compressed by the threads of the pool returned by `pool`, only called for
+/// gzip, and zstd by `threads` threads.
+fn compressed(
+    file: File,
+    filename: &str,
+    level: u32,
+    threads: usize,
+    pool: impl FnOnce() -> Option<Arc<CompressionPool>>,
+) -> Result<Box<dyn FinishWrite>> {
+    Ok(match output_format(filename) {
+        Format::No => Box::new(file),
+        Format::Gzip => Box::new(BlockWriter::gzip(BufWriter::new(file), pool()).level(level)),
+        Format::Bzip => Box::new(BzEncoder::new(
+            BufWriter::new(file),
+            bzip2::Compression::new(level),
+        )),
+        Format::Lzma => Box::new(XzEncoder::new(BufWriter::new(file), level)),
+        Format::Zstd => {
+            let mut encoder = zstd::Encoder::new(BufWriter::new(file), level as i32)?;
+            if threads > 1 {
+                encoder.multithread(threads as u32)?;
+            }
+            Box::new(encoder)
+        }
+    })
+}
+
+/// Maximum amount of uncompressed data in a BGZF block, as in htslib.
+const BGZF_BLOCK_SIZE: usize = 0xff00;
+/// Empty block marking the end of a BGZF file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0, b'B', b'C', 0x02, 0, 0x1b, 0, 0x03, 0, 0,
+    0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Amount of uncompressed data in each gzip member written by `BlockWriter::gzip`.
+const GZIP_BLOCK_SIZE: usize = 1 << 20;
+
+/// Compresses a BGZF block, i.e. a gzip member recording its compressed size in its header.
+fn bgzf_block(data: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let mut block = vec![
+        0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0, b'B', b'C', 0x02, 0, 0, 0,
+    ];
+    let mut encoder = DeflateEncoder::new(block, level);
+    encoder.write_all(data)?;
+    block = encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.update(data);
+    block.extend(crc.sum().to_le_bytes());
+    block.extend((data.len() as u32).to_le_bytes());
+    // The block size minus one is recorded in the BC extra field.
+    let block_size = (block.len() - 1) as u16;
+    block[16..18].copy_from_slice(&block_size.to_le_bytes());
+    Ok(block)
+}
+
+/// Compresses a block as a standalone gzip member.
+fn gzip_block(data: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Function compressing a block at a given level.
+type CompressBlock = fn(&[u8], Compression) -> Result<Vec<u8>>;
+
+type CompressJob = (Vec<u8>, CompressBlock, Compression, Sender<Result<Vec<u8>>>);
+
+/// Threads compressing the blocks of the `BlockWriter`s sharing them,
+/// so that writing several outputs does not multiply the compression threads.
+pub struct CompressionPool {
+    jobs: Option<SyncSender<CompressJob>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CompressionPool {
+    /// Starts `threads` compression threads, or none if a single thread is requested,
+    /// the blocks being then compressed by the writing thread.
+    pub fn new(threads: usize) -> Option<Arc<Self>> {
+        if threads <= 1 {
+            return None;
+        }
+        let (sender, receiver) = sync_channel::<CompressJob>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok((data, compress, level, result)) => {
+                            let _ = result.send(compress(&data, level));
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        Some(Arc::new(Self {
+            jobs: Some(sender),
+            workers,
+        }))
+    }
+
+    fn threads(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+impl Drop for CompressionPool {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Writer compressing its data by independent blocks, concatenated in order, so that the blocks
+/// can be compressed by several threads (like `pigz`) while remaining a valid gzip stream.
+/// Its stream is only complete once [`FinishWrite::finish`] is called.
+pub struct BlockWriter<W: Write> {
+    writer: W,
+    block: Vec<u8>,
+    block_size: usize,
+    compress: CompressBlock,
+    level: Compression,
+    trailer: &'static [u8],
+    pool: Option<Arc<CompressionPool>>,
+    pending: VecDeque<Receiver<Result<Vec<u8>>>>,
+    max_pending: usize,
+    finished: bool,
+}
+
+impl<W: Write> BlockWriter<W> {
+    fn new(
+        writer: W,
+        block_size: usize,
+        compress: CompressBlock,
+        trailer: &'static [u8],
+        pool: Option<Arc<CompressionPool>>,
+    ) -> Self {
+        Self {
+            writer,
+            block: Vec::with_capacity(block_size),
+            block_size,
+            compress,
+            level: Compression::new(COMPRESSION_LEVEL),
+            trailer,
+            max_pending: 2 * pool.as_ref().map_or(1, |pool| pool.threads()),
+            pool,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Writer compressing its data in BGZF blocks, i.e. gzip members of at most 64 KB recording
+    /// their compressed size, which tools such as `samtools faidx` can index.
+    pub fn bgzf(writer: W, threads: usize) -> Self {
+        Self::bgzf_with_pool(writer, CompressionPool::new(threads))
+    }
+
+    fn bgzf_with_pool(writer: W, pool: Option<Arc<CompressionPool>>) -> Self {
+        Self::new(writer, BGZF_BLOCK_SIZE, bgzf_block, &BGZF_EOF, pool)
+    }
+
+    /// Writer compressing its data in gzip members of 1 MB.
+    fn gzip(writer: W, pool: Option<Arc<CompressionPool>>) -> Self {
+        Self::new(writer, GZIP_BLOCK_SIZE, gzip_block, &[], pool)
+    }
+
+    /// Compresses the blocks at `level` (from 0 to 9) instead of the default level.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Compression::new(level);
+        self
+    }
+
+    /// Writes the oldest block being compressed once it is ready.
+    fn write_pending(&mut self) -> Result<()> {
+        if let Some(result) = self.pending.pop_front() {
+            let block = result.recv().map_err(Error::other)??;
+            self.writer.write_all(&block)?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.block, Vec::with_capacity(self.block_size));
+        match self.pool.as_ref().and_then(|pool| pool.jobs.as_ref()) {
+            Some(jobs) => {
+                let (sender, receiver) = channel();
+                jobs.send((data, self.compress, self.level, sender))
+                    .map_err(Error::other)?;
+                self.pending.push_back(receiver);
+                while self.pending.len() > self.max_pending {
+                    self.write_pending()?;
+                }
+            }
+            None => {
+                let block = (self.compress)(&data, self.level)?;
+                self.writer.write_all(&block)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> FinishWrite for BlockWriter<W> {
+    /// Writes the remaining data followed by the end-of-file marker, if any.
+    fn finish(&mut self) -> Result<()> {
+        if !self.finished {
+            self.flush()?;
+            self.writer.write_all(self.trailer)?;
+            self.writer.flush()?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = buf.len().min(self.block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == self.block_size {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_block()?;
+        while !self.pending.is_empty() {
+            self.write_pending()?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Output files of a run, which are written under a temporary name when `atomic`
+/// and renamed once the run succeeds, so that an interrupted run never leaves
+/// a truncated file under the final name.
+/// The standard output, named `-`, is written directly and left out of the committed outputs.
+/// The writers must be finished before committing the outputs.
+pub struct Outputs {
+    atomic: bool,
+    overwrite: bool,
+    level: Option<u32>,
+    threads: usize,
+    /// Compression threads, started when the first gzip or BGZF output is created.
+    pool: Option<Arc<CompressionPool>>,
+    filenames: Vec<String>,
+}
+
+impl Outputs {
+    pub fn new(atomic: bool) -> Self {
+        Self {
+            atomic,
+            overwrite: true,
+            level: None,
+            threads: 1,
+            pool: None,
+            filenames: Vec::new(),
+        }
+    }
+
+    /// Compresses the outputs at `level` instead of the default level of their format.
+    pub fn level(mut self, level: Option<u32>) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Returns the level at which `filename` is compressed in `format`,
+    /// or an error if it is not supported by this format.
+    fn level_of(&self, filename: &str, format: Format) -> Result<u32> {
+        let level = self.level.unwrap_or(COMPRESSION_LEVEL);
+        let levels = levels(format);
+        if !levels.contains(&level) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The compression level of {filename} must be between {} and {}",
+                    levels.start(),
+                    levels.end()
+                ),
+            ));
+        }
+        Ok(level)
+    }
+
+    /// Compresses the outputs on `threads` threads shared by all of them (gzip, BGZF and zstd only).
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Returns the compression threads shared by the outputs, starting them if needed.
+    fn pool(&mut self) -> Option<Arc<CompressionPool>> {
+        if self.pool.is_none() {
+            self.pool = CompressionPool::new(self.threads);
+        }
+        self.pool.clone()
+    }
+
+    /// Replaces the existing files if `overwrite`, or refuses to create them otherwise.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Fails if `filename` exists and cannot be overwritten,
+    /// or if its format does not support the compression level.
+    pub fn check(&self, filename: &str) -> Result<()> {
+        let format = output_format(filename);
+        if format != Format::No {
+            self.level_of(filename, format)?;
+        }
+        if !self.overwrite && filename != STDIO && Path::new(filename).exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{filename} already exists, use --force to overwrite it"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the path to which `filename` is written until the outputs are committed.
+    pub fn path(&self, filename: &str) -> String {
+        if self.atomic && filename != STDIO {
+            format!("{filename}.tmp")
+        } else {
+            filename.to_owned()
+        }
+    }
+
+    /// Creates the output file `filename`, compressed according to its extension.
+    pub fn create(&mut self, filename: &str) -> Result<Box<dyn FinishWrite>> {
+        if filename == STDIO {
+            return Ok(Box::new(stdout()));
+        }
+        self.check(filename)?;
+        let file = File::create(self.path(filename))?;
+        self.filenames.push(filename.to_owned());
+        let level = self.level_of(filename, output_format(filename))?;
+        compressed(file, filename, level, self.threads, || self.pool())
+    }
+
+    /// Creates the output file `filename`, compressed in BGZF blocks whatever its extension.
+    pub fn create_bgzf(&mut self, filename: &str) -> Result<Box<dyn FinishWrite>> {
+        let level = self.level_of(filename, Format::Gzip)?;
+        let pool = self.pool();
+        if filename == STDIO {
+            return Ok(Box::new(
+                BlockWriter::bgzf_with_pool(stdout(), pool).level(level),
+            ));
+        }
+        self.check(filename)?;
+        let file = File::create(self.path(filename))?;
+        self.filenames.push(filename.to_owned());
+        Ok(Box::new(
+            BlockWriter::bgzf_with_pool(BufWriter::new(file), pool).level(level),
+        ))
+    }
+
+    /// Moves the outputs to their final names and returns these names.
+    pub fn commit(self) -> Result<Vec<String>> {
+        if self.atomic {
+            for filename in self.filenames.iter() {
+                rename(self.path(filename), filename)?;
+            }
+        }
+        Ok(self.filenames)
+    }
+}
+
+/// Writes the SHA-256 checksums of `filenames` to `done_filename` in the format of `sha256sum`,
+/// going through a temporary file so that the done file only appears once complete.
+pub fn write_done_file(done_filename: &str, filenames: &[String]) -> Result<()> {
+    let mut outputs = Outputs::new(true);
+    let mut writer = BufWriter::new(outputs.create(done_filename)?);
+    for filename in filenames {
+        let mut hasher = Sha256::new();
+        copy(&mut File::open(filename)?, &mut hasher)?;
+        let checksum: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        writeln!(writer, "{checksum}  {filename}")?;
+    }
+    writer.finish()?;
+    drop(writer);
+    outputs.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read_to_string, remove_file};
+    use std::io::Read;
+    use std::path::Path;
+
+    #[test]
+    fn test_atomic_outputs() {
+        let dir = std::env::temp_dir();
+        let filename = dir.join(format!("brrr_test_atomic_{}.txt", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let done_filename = dir.join(format!("brrr_test_atomic_{}.done", std::process::id()));
+        let done_filename = done_filename.to_str().unwrap();
+        let mut outputs = Outputs::new(true);
+        let mut writer = outputs.create(filename).unwrap();
+        writer.write_all(b"abc").unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        assert!(!Path::new(filename).exists());
+        let filenames = outputs.commit().unwrap();
+        assert_eq!(read_to_string(filename).unwrap(), "abc");
+        write_done_file(done_filename, &filenames).unwrap();
+        assert_eq!(
+            read_to_string(done_filename).unwrap(),
+            format!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {filename}\n"
+            )
+        );
+        let mut outputs = Outputs::new(true).overwrite(false);
+        let error = outputs.create(filename).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert!(outputs.create(STDIO).is_ok());
+        remove_file(filename).unwrap();
+        remove_file(done_filename).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_output() {
+        let content = ">r0\nACGTACGT\n".repeat(300_000);
+        for ext in COMPRESSION_EXTENSIONS {
+            let filename = std::env::temp_dir()
+                .join(format!("brrr_test_output_{}.fa.{ext}", std::process::id()));
+            let filename = filename.to_str().unwrap();
+            for threads in [1, 3] {
+                let mut writer = Outputs::new(false)
+                    .threads(threads)
+                    .create(filename)
+                    .unwrap();
+                writer.write_all(content.as_bytes()).unwrap();
+                writer.finish().unwrap();
+                drop(writer);
+                let mut decompressed = String::new();
+                crate::reads::open_file(filename)
+                    .read_to_string(&mut decompressed)
+                    .unwrap();
+                assert!(decompressed == content);
+            }
+            remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compression_level() {
+        let outputs = Outputs::new(false).level(Some(0));
+        assert!(outputs.check("brrr_test_level.fa").is_ok());
+        assert!(outputs.check("brrr_test_level.fa.gz").is_ok());
+        let error = outputs.check("brrr_test_level.fa.bz2").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let outputs = Outputs::new(false).level(Some(19));
+        assert!(outputs.check("brrr_test_level.fa.zst").is_ok());
+        assert!(outputs.check("brrr_test_level.fa.xz").is_err());
+        // Lower levels compress faster but less.
+        let content = b">r0\nACGTTGCAACGGTACCATGA\n".repeat(10_000);
+        let sizes: Vec<_> = [1, 9]
+            .map(|level| {
+                let mut gzip = Vec::new();
+                let mut writer = BlockWriter::gzip(&mut gzip, None).level(level);
+                writer.write_all(&content).unwrap();
+                writer.finish().unwrap();
+                drop(writer);
+                gzip.len()
+            })
+            .into();
+        assert!(sizes[0] > sizes[1]);
+    }
+
+    #[test]
+    fn test_shared_compression() {
+        let dir = std::env::temp_dir();
+        let filenames = [
+            dir.join(format!("brrr_test_shared_1_{}.fa.gz", std::process::id())),
+            dir.join(format!("brrr_test_shared_2_{}.fa.gz", std::process::id())),
+        ];
+        let filenames = filenames.map(|filename| filename.to_str().unwrap().to_owned());
+        let contents = [">r0\nACGTACGT\n", ">r1\nTTTTTTTT\n"].map(|record| record.repeat(200_000));
+        let mut outputs = Outputs::new(false).threads(3);
+        let mut writers: Vec<_> = filenames
+            .iter()
+            .map(|filename| outputs.create(filename).unwrap())
+            .collect();
+        // The blocks of both outputs are compressed by the same threads in between.
+        for (chunk0, chunk1) in contents[0]
+            .as_bytes()
+            .chunks(100_000)
+            .zip(contents[1].as_bytes().chunks(100_000))
+        {
+            writers[0].write_all(chunk0).unwrap();
+            writers[1].write_all(chunk1).unwrap();
+        }
+        for writer in writers.iter_mut() {
+            writer.finish().unwrap();
+        }
+        drop(writers);
+        for (filename, content) in filenames.iter().zip(contents) {
+            let mut decompressed = String::new();
+            crate::reads::open_file(filename)
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert!(decompressed == content);
+            remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_finish_error() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> Result<usize> {
+                Err(Error::new(ErrorKind::StorageFull, "full"))
+            }
+            fn flush(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+        impl FinishWrite for Full {
+            fn finish(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+        for threads in [1, 3] {
+            let mut writer = BlockWriter::bgzf(Full, threads);
+            writer.write_all(b">r0\nACGT\n").unwrap();
+            let error = writer.finish().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::StorageFull);
+        }
+        // The encoders only write their stream once finished.
+        let mut writers: Vec<Box<dyn FinishWrite>> = vec![
+            Box::new(BzEncoder::new(Full, bzip2::Compression::default())),
+            Box::new(XzEncoder::new(Full, COMPRESSION_LEVEL)),
+            Box::new(zstd::Encoder::new(Full, COMPRESSION_LEVEL as i32).unwrap()),
+        ];
+        for writer in writers.iter_mut() {
+            writer.write_all(b">r0\nACGT\n").unwrap();
+            let error = writer.finish().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::StorageFull);
+        }
+    }
+
+    #[test]
+    fn test_bgzf() {
+        let content = b">r0\nACGTACGT\n".repeat(10_000);
+        let mut bgzf = Vec::new();
+        let mut writer = BlockWriter::bgzf(&mut bgzf, 1);
+        writer.write_all(&content).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        assert!(bgzf.ends_with(&BGZF_EOF));
+        let mut parallel = Vec::new();
+        let mut writer = BlockWriter::bgzf(&mut parallel, 3);
+        writer.write_all(&content).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        assert!(parallel == bgzf);
+        // Each block records its own size, so that blocks can be located without decompressing.
+        let mut pos = 0;
+        while pos < bgzf.len() {
+            assert_eq!(&bgzf[pos + 12..pos + 14], b"BC");
+            pos += u16::from_le_bytes([bgzf[pos + 16], bgzf[pos + 17]]) as usize + 1;
+        }
+        assert_eq!(pos, bgzf.len());
+        let mut decompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(&bgzf[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, content);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::kmer::{Base, Kmer};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Largest change of the offset between two mates that corrections with indels can explain.
+const MAX_OFFSET_SHIFT: usize = 2;
+
+/// Returns the most frequent offset between the positions of the shared `solid` k-mers of `mate1`
+/// and of the reverse complement of `mate2`, i.e. where the reverse complement of `mate2` starts
+/// relative to `mate1`, or `None` if the mates share no solid k-mer (e.g. they do not overlap).
+pub fn mate_offset<const K: usize, T: Base, KmerT, F>(
+    mate1: &[u8],
+    mate2: &[u8],
+    solid: F,
+) -> Option<isize>
+where
+    KmerT: Kmer<K, T> + Hash + Eq,
+    F: Fn(KmerT) -> bool,
+{
+    let kmers2: Vec<KmerT> = KmerT::iter_from_nucs(mate2.iter()).collect();
+    let positions: HashMap<KmerT, usize> = kmers2
+        .iter()
+        .enumerate()
+        .map(|(j, kmer)| (kmer.rev_comp(), kmers2.len() - 1 - j))
+        .collect();
+    let mut offsets: HashMap<isize, usize> = HashMap::new();
+    for (i, kmer) in KmerT::iter_from_nucs(mate1.iter()).enumerate() {
+        if let Some(&j) = positions.get(&kmer) {
+            if solid(kmer) {
+                *offsets.entry(i as isize - j as isize).or_default() += 1;
+            }
+        }
+    }
+    offsets
+        .into_iter()
+        .max_by_key(|&(offset, count)| (count, -offset.abs()))
+        .map(|(offset, _)| offset)
+}
+
+/// Returns `true` if mates that overlapped `before` the correction no longer overlap `after` it
+/// at about the same offset, e.g. because one of them was corrected into another repeat copy.
+pub fn diverged(before: Option<isize>, after: Option<isize>) -> bool {
+    before.is_some_and(|before| after.is_none_or(|after| before.abs_diff(after) > MAX_OFFSET_SHIFT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmer::RawKmer;
+    use crate::testing::rev_comp;
+
+    const K: usize = 7;
+    type KmerT = RawKmer<K, u16>;
+
+    #[test]
+    fn test_mate_offset() {
+        let fragment = b"ACGTTGCAAGGCTAGCTTACGGATCCAGTAGCATGCAAGT";
+        let mate1 = &fragment[..30];
+        let mate2 = rev_comp(&fragment[10..]);
+        let offset =
+            |mate1: &[u8], mate2: &[u8]| mate_offset::<K, u16, KmerT, _>(mate1, mate2, |_| true);
+        assert_eq!(offset(mate1, &mate2), Some(10));
+        assert_eq!(offset(mate1, &rev_comp(&fragment[25..])), None);
+        assert_eq!(
+            mate_offset::<K, u16, KmerT, _>(mate1, &mate2, |_| false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_diverged() {
+        assert!(!diverged(None, None));
+        assert!(!diverged(None, Some(3)));
+        assert!(!diverged(Some(10), Some(11)));
+        assert!(diverged(Some(10), Some(20)));
+        assert!(diverged(Some(10), None));
+    }
+}
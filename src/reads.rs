@@ -1,24 +1,835 @@
-use seq_io::fasta;
-use seq_io::parallel::read_process_fasta_records;
+use crate::bam::open_alignment_files;
+use crate::{logs, pair_name};
+use ahash::RandomState;
+use niffler::send::compression::Format;
+use seq_io::parallel::{
+    read_process_fasta_records, read_process_fastq_records, read_process_recordsets,
+    RecordSetReader,
+};
 pub use seq_io::BaseRecord;
-use std::fs::File;
+use seq_io::{fasta, fastq};
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::fs::{metadata, read_dir, File};
+use std::io::{copy, sink, stdin, BufReader, Cursor, Error, ErrorKind, Read, Result, Write};
+use std::mem::swap;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
 use std::slice::Iter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Default size (in bytes) of the batches of records sent to the worker threads.
+pub const DEFAULT_BATCH_SIZE: usize = 1 << 16;
+
+/// File name standing for the standard input or output.
+pub const STDIO: &str = "-";
+
+/// Time after which ordered processing warns that it is still waiting on the same record set
+/// while the reorder window is full.
+pub const STALL_WARNING: Duration = Duration::from_secs(60);
+
+/// Extensions of the sequence files found in the directories given as inputs.
+const SEQUENCE_EXTENSIONS: [&str; 7] = ["fasta", "fa", "fna", "fastq", "fq", "bam", "sam"];
+
+/// Prefixes of the URLs of the inputs streamed from the network.
+const REMOTE_SCHEMES: [&str; 3] = ["http://", "https://", "s3://"];
+
+static STDIN_CONTENT: OnceLock<Arc<[u8]>> = OnceLock::new();
+
+/// Size (in bytes) of the decompressed sample used to estimate the compression ratio of a file.
+const SAMPLE_SIZE: u64 = 1 << 24;
+
+/// Decompresses a stream on the fly if it is compressed with gzip, bzip2, xz or zstd,
+/// the codec being detected from its magic bytes rather than from a file extension.
+fn decompress<'a, R: Read + Send + 'a>(reader: R) -> (Box<dyn Read + Send + 'a>, Format) {
+    niffler::send::get_reader(Box::new(reader)).expect("Failed to detect compression")
+}
+
+/// Returns the decompressed content of the standard input, which is buffered in memory
+/// on first use since it cannot be read again by the following passes.
+fn stdin_content() -> Arc<[u8]> {
+    STDIN_CONTENT
+        .get_or_init(|| {
+            let mut content = Vec::new();
+            decompress(stdin())
+                .0
+                .read_to_end(&mut content)
+                .expect("Failed to read standard input");
+            content.into()
+        })
+        .clone()
+}
+
+fn is_stdin<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref() == Path::new(STDIO)
+}
+
+/// Returns `true` if the input is a URL to stream from the network.
+pub fn is_remote<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref().to_string_lossy();
+    REMOTE_SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// Returns the file name of a remote input, without its query, under which its outputs are named
+/// in the current directory.
+pub fn remote_filename(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Input streamed from an `http(s)://` URL by `curl`, or from an `s3://` URL by the AWS CLI.
+/// Since the reads are read once per pass, the input is downloaded again by each pass
+/// rather than staged on the disk.
+pub struct Remote {
+    url: String,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Remote {
+    pub fn open(url: &str) -> Result<Self> {
+        let command = if url.starts_with("s3://") {
+            let mut command = Command::new("aws");
+            command.args(["s3", "cp", "--only-show-errors", url, "-"]);
+            command
+        } else {
+            let mut command = Command::new("curl");
+            command.args(["--silent", "--show-error", "--fail", "--location", url]);
+            command
+        };
+        Self::with_command(url, command)
+    }
+
+    /// Streams `url` from the standard output of `command`, which must exit with an error
+    /// status if the download fails.
+    fn with_command(url: &str, mut command: Command) -> Result<Self> {
+        let mut child = command.stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(Self {
+            url: url.to_owned(),
+            child,
+            stdout,
+        })
+    }
+}
+
+impl Read for Remote {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            return Err(Error::other(format!("Failed to download {}", self.url)));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        // The download may be stopped early, e.g. once the format of the input is detected.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Opens a file, decompressing it on the fly if it is compressed.
+/// The standard input is read from memory when the file name is `-`,
+/// and URLs are streamed from the network.
+pub fn open_file<P: AsRef<Path>>(path: P) -> Box<dyn Read + Send> {
+    if is_stdin(&path) {
+        return Box::new(Cursor::new(stdin_content()));
+    }
+    if is_remote(&path) {
+        let url = path.as_ref().to_string_lossy();
+        let remote = Remote::open(&url).expect("Failed to stream remote input");
+        return decompress(BufReader::new(remote)).0;
+    }
+    let file = File::open(path).expect("Failed to open file");
+    decompress(BufReader::new(file)).0
+}
+
+/// Returns `true` if the name of a file has the extension of a sequence file,
+/// optionally followed by the extension of a compression format.
+fn is_sequence_file(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    let name = [".gz", ".bz2", ".xz", ".zst"]
+        .iter()
+        .fold(&name[..], |name, ext| name.trim_end_matches(ext));
+    Path::new(name)
+        .extension()
+        .is_some_and(|ext| SEQUENCE_EXTENSIONS.iter().any(|e| ext == *e))
+}
+
+/// Returns `true` if `name` matches `pattern`, in which `*` stands for any sequence of characters
+/// and `?` for any single character.
+fn matches_pattern(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => {
+            (0..=name.len()).any(|skipped| matches_pattern(rest, &name[skipped..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => matches_pattern(rest, name),
+        (Some((c, rest)), Some((d, name))) => c == d && matches_pattern(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+/// Lists the files of `dir` whose name satisfies `filter`, sorted by name.
+fn list_files(dir: &Path, filter: impl Fn(&Path) -> bool) -> Vec<String> {
+    let mut files: Vec<_> = read_dir(dir)
+        .expect("Failed to read input directory")
+        .map(|entry| entry.expect("Failed to read input directory").path())
+        .filter(|path| path.is_file() && filter(path))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Replaces the directories given as inputs by the sequence files they contain,
+/// and the glob patterns (e.g. `data/*.fq.gz`) by the files matching them.
+pub fn expand_inputs(inputs: &[String]) -> Vec<String> {
+    inputs
+        .iter()
+        .flat_map(|input| {
+            let path = Path::new(input);
+            let files = if path.is_dir() {
+                list_files(path, is_sequence_file)
+            } else if !is_remote(input) && input.contains(['*', '?']) && !path.exists() {
+                let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                let pattern = path.file_name().unwrap_or_default().as_encoded_bytes();
+                list_files(dir.unwrap_or(Path::new(".")), |file| {
+                    matches_pattern(pattern, file.file_name().unwrap().as_encoded_bytes())
+                })
+            } else {
+                return vec![input.clone()];
+            };
+            assert!(!files.is_empty(), "No sequence file found in {input}");
+            files
+        })
+        .collect()
+}
+
+/// Ends a stream with a newline if it lacks one, so that the last record of a file
+/// does not run into the first record of the next one.
+/// A compressed stream cut short ends there too, leaving its last record truncated.
+struct TerminatedReader<R> {
+    inner: R,
+    last: u8,
+    done: bool,
+}
+
+impl<R: Read> Read for TerminatedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = match self.inner.read(buf) {
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => 0,
+            result => result?,
+        };
+        if n > 0 {
+            self.last = buf[n - 1];
+            return Ok(n);
+        }
+        self.done = true;
+        if self.last == b'\n' {
+            return Ok(0);
+        }
+        buf[0] = b'\n';
+        Ok(1)
+    }
+}
+
+/// Opens a file like [`open_file`], ending it with a newline if it lacks one.
+fn open_terminated<P: AsRef<Path>>(path: P) -> Box<dyn Read + Send> {
+    Box::new(TerminatedReader {
+        inner: open_file(path),
+        last: b'\n',
+        done: false,
+    })
+}
+
+/// Opens files as a single stream, decompressing each of them if needed.
+pub fn open_files<P: AsRef<Path>>(paths: &[P]) -> Box<dyn Read + Send> {
+    paths
+        .iter()
+        .map(open_terminated)
+        .reduce(|stream, next| Box::new(stream.chain(next)))
+        .unwrap_or_else(|| Box::new(std::io::empty()))
+}
+
+/// Truncated record skipped at the end of an input.
+#[derive(Debug, PartialEq)]
+struct Truncation {
+    input: Option<String>,
+    /// Offset (in bytes) of the record in the input.
+    offset: u64,
+}
+
+/// Truncated records skipped at the end of the inputs, shared by the readers of every pass
+/// over the inputs so that each of them is reported once.
+#[derive(Clone, Debug, Default)]
+pub struct Truncations(Arc<Mutex<Vec<Truncation>>>);
+
+impl Truncations {
+    /// Records the truncated record at `offset` in the input `name`, reporting it the first time.
+    fn add(&self, name: Option<&str>, offset: u64) {
+        let truncation = Truncation {
+            input: name.map(str::to_owned),
+            offset,
+        };
+        let mut truncations = self.0.lock().unwrap();
+        if truncations.contains(&truncation) {
+            return;
+        }
+        truncations.push(truncation);
+        let input = name.unwrap_or("the input");
+        logs::warn(
+            "truncated_input",
+            format!("The last record of {input} (at byte {offset}) is truncated, it was skipped"),
+            json!({ "input": name, "offset": offset }),
+        );
+    }
+
+    /// Returns the offsets of the truncated records skipped so far, in the order they were found.
+    pub fn offsets(&self) -> Vec<u64> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|truncation| truncation.offset)
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// Tracks the start of the last four lines of a stream, which hold its last FASTQ record,
+/// so that a truncated record can be told apart from an invalid one in the middle of the input.
+struct TailReader<R> {
+    inner: R,
+    offset: u64,
+    /// Offsets of the last five line breaks read.
+    line_breaks: VecDeque<u64>,
+    last: u8,
+    peeked: Option<u8>,
+    ended: bool,
+    /// Offset of the last four lines once the end of the stream is known, `u64::MAX` before.
+    last_record: Arc<AtomicU64>,
+}
+
+impl<R: Read> TailReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            line_breaks: VecDeque::new(),
+            last: b'\n',
+            peeked: None,
+            ended: false,
+            last_record: Arc::new(AtomicU64::new(u64::MAX)),
+        }
+    }
+}
+
+impl<R: Read> Read for TailReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut n = 0;
+        if let Some(byte) = self.peeked.take() {
+            buf[0] = byte;
+            n = 1;
+        }
+        if !self.ended {
+            n += self.inner.read(&mut buf[n..])?;
+        }
+        // Reading one byte ahead tells whether these bytes are the last ones.
+        let mut next = [0];
+        if self.ended || n == 0 || self.inner.read(&mut next)? == 0 {
+            self.ended = true;
+        } else {
+            self.peeked = Some(next[0]);
+        }
+        let last_breaks: Vec<_> = (0..n).rev().filter(|&i| buf[i] == b'\n').take(5).collect();
+        let offset = self.offset;
+        self.line_breaks
+            .extend(last_breaks.iter().rev().map(|&i| offset + i as u64));
+        let excess = self.line_breaks.len().saturating_sub(5);
+        self.line_breaks.drain(..excess);
+        self.offset += n as u64;
+        if n > 0 {
+            self.last = buf[n - 1];
+        }
+        if self.ended {
+            // The last line may lack its line break.
+            let breaks = if self.last == b'\n' { 5 } else { 4 };
+            let start = match self.line_breaks.len().checked_sub(breaks) {
+                Some(i) => self.line_breaks[i] + 1,
+                None => 0,
+            };
+            self.last_record.store(start, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+/// Skips the truncated record ending the input that caused `error`, recording it in `truncations`,
+/// or returns `error` if it was caused by another problem.
+/// A record is truncated if the input ends in the middle of it, or if it is the last one
+/// but its qualities are shorter than its sequence (as its last line was cut).
+fn skip_truncated(
+    error: fastq::Error,
+    last_record: &AtomicU64,
+    name: Option<&str>,
+    truncations: &Truncations,
+) -> std::result::Result<(), fastq::Error> {
+    let start = match error.kind() {
+        fastq::ErrorKind::UnexpectedEnd { pos } => pos.record_position().map(|pos| pos.byte()),
+        fastq::ErrorKind::UnequalLengths { pos, seq, qual } if qual < seq => pos
+            .record_position()
+            .map(|pos| pos.byte())
+            .filter(|&start| start == last_record.load(Ordering::Relaxed)),
+        _ => None,
+    };
+    let Some(start) = start else {
+        return Err(error);
+    };
+    truncations.add(name, start);
+    Ok(())
+}
+
+/// FASTQ reader of a single input, with the offset of its last record once its end is known.
+struct InputReader {
+    reader: fastq::Reader<Box<dyn Read + Send>>,
+    last_record: Arc<AtomicU64>,
+    name: Option<String>,
+}
+
+/// FASTQ reader going through its inputs one after the other, skipping the truncated record
+/// ending each of them instead of failing on it.
+struct TolerantReader {
+    current: Option<InputReader>,
+    inputs: VecDeque<(Option<String>, Box<dyn Read + Send>)>,
+    batch_size: usize,
+    truncations: Truncations,
+}
+
+impl TolerantReader {
+    fn new(inputs: Vec<(Option<String>, Box<dyn Read + Send>)>, batch_size: usize) -> Self {
+        let mut reader = Self {
+            current: None,
+            inputs: inputs.into(),
+            batch_size,
+            truncations: Truncations::default(),
+        };
+        reader.next_input();
+        reader
+    }
+
+    /// Starts reading the next input, returning `false` once they have all been read.
+    fn next_input(&mut self) -> bool {
+        self.current = self.inputs.pop_front().map(|(name, input)| {
+            let tail = TailReader::new(input);
+            InputReader {
+                last_record: tail.last_record.clone(),
+                reader: fastq::Reader::with_capacity(Box::new(tail), self.batch_size),
+                name,
+            }
+        });
+        self.current.is_some()
+    }
+}
+
+impl RecordSetReader for TolerantReader {
+    type RecordSet = fastq::RecordSet;
+    type Err = fastq::Error;
+
+    fn fill_data(&mut self, rset: &mut Self::RecordSet) -> std::result::Result<bool, Self::Err> {
+        while let Some(input) = self.current.as_mut() {
+            match input.reader.read_record_set(rset) {
+                Ok(true) => return Ok(true),
+                Ok(false) => (),
+                Err(error) => {
+                    skip_truncated(
+                        error,
+                        &input.last_record,
+                        input.name.as_deref(),
+                        &self.truncations,
+                    )?;
+                    // The complete records found before the truncated one are still in the set.
+                    if !rset.is_empty() {
+                        self.next_input();
+                        return Ok(true);
+                    }
+                }
+            }
+            self.next_input();
+        }
+        Ok(false)
+    }
+}
+
+/// Counts the bytes read through a reader.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Returns the size of the content of a file once decompressed.
+/// For compressed files, it is estimated from the compression ratio of the beginning of the file.
+pub fn content_size<P: AsRef<Path>>(path: P) -> usize {
+    if is_stdin(&path) {
+        return stdin_content().len();
+    }
+    assert!(
+        !is_remote(&path),
+        "The size of remote inputs is unknown, give the memory to allocate with --memory"
+    );
+    let len = metadata(&path).expect("Failed to get input size").len();
+    let mut compressed = CountingReader {
+        inner: File::open(path).expect("Failed to open file"),
+        count: 0,
+    };
+    let (content, format) = decompress(&mut compressed);
+    if format == Format::No {
+        return len as usize;
+    }
+    let decompressed =
+        copy(&mut content.take(SAMPLE_SIZE), &mut sink()).expect("Failed to decompress file");
+    if compressed.count == 0 {
+        return 0;
+    }
+    (len as f64 * decompressed as f64 / compressed.count as f64) as usize
+}
 
 pub struct Fasta {
-    reader: fasta::Reader<File>,
+    reader: fasta::Reader<Box<dyn Read + Send>>,
+    ordered: bool,
+    stall_warning: Duration,
 }
 
 impl Fasta {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        Self::from_file_with_batch_size(path, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Records are processed in parallel by batches filling a buffer of `batch_size` bytes
+    /// (grown to fit a record if needed), so larger batches mean fewer, larger work units.
+    pub fn from_file_with_batch_size<P: AsRef<Path>>(path: P, batch_size: usize) -> Self {
+        Self::from_reader_with_batch_size(open_file(path), batch_size)
+    }
+
+    /// Reads the records from any source, e.g. a stream generated on the fly.
+    pub fn from_reader_with_batch_size<R: Read + Send + 'static>(
+        reader: R,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            reader: fasta::Reader::with_capacity(Box::new(reader), batch_size),
+            ordered: false,
+            stall_warning: STALL_WARNING,
+        }
+    }
+}
+
+pub struct Fastq {
+    reader: TolerantReader,
+    ordered: bool,
+    stall_warning: Duration,
+}
+
+impl Fastq {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        Self::from_file_with_batch_size(path, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Records are processed in parallel by batches filling a buffer of `batch_size` bytes
+    /// (grown to fit a record if needed), so larger batches mean fewer, larger work units.
+    pub fn from_file_with_batch_size<P: AsRef<Path>>(path: P, batch_size: usize) -> Self {
+        Self::from_files_with_batch_size(&[path], batch_size)
+    }
+
+    /// Reads several files one after the other, each of them possibly ending with a truncated
+    /// record which is skipped before reading the next one.
+    pub fn from_files_with_batch_size<P: AsRef<Path>>(paths: &[P], batch_size: usize) -> Self {
+        let inputs = paths
+            .iter()
+            .map(|path| {
+                let name = path.as_ref().to_string_lossy().into_owned();
+                (Some(name), open_terminated(path))
+            })
+            .collect();
+        Self {
+            reader: TolerantReader::new(inputs, batch_size),
+            ordered: false,
+            stall_warning: STALL_WARNING,
+        }
+    }
+
+    /// Reads the records from any source, e.g. a stream generated on the fly.
+    pub fn from_reader_with_batch_size<R: Read + Send + 'static>(
+        reader: R,
+        batch_size: usize,
+    ) -> Self {
         Self {
-            reader: fasta::Reader::from_path(path).expect("Failed to open file"),
+            reader: TolerantReader::new(vec![(None, Box::new(reader))], batch_size),
+            ordered: false,
+            stall_warning: STALL_WARNING,
+        }
+    }
+
+    /// Records the truncated records skipped at the end of the inputs in `truncations`,
+    /// which may be shared with the readers of other passes over the same inputs.
+    pub fn truncations(mut self, truncations: &Truncations) -> Self {
+        self.reader.truncations = truncations.clone();
+        self
+    }
+}
+
+/// Reads of alignment files (BAM or SAM, typically unaligned), converted to FASTQ as they are parsed.
+pub struct Bam {
+    fastq: Fastq,
+}
+
+impl Bam {
+    pub fn from_file<P: AsRef<str>>(path: P) -> Self {
+        Self::from_files_with_batch_size(&[path], DEFAULT_BATCH_SIZE)
+    }
+
+    /// Reads several alignment files as one, each of them being either BAM or SAM.
+    pub fn from_files_with_batch_size<P: AsRef<str>>(paths: &[P], batch_size: usize) -> Self {
+        Self {
+            fastq: Fastq::from_reader_with_batch_size(open_alignment_files(paths), batch_size),
+        }
+    }
+
+    /// Records the truncated records skipped at the end of the inputs in `truncations`.
+    pub fn truncations(self, truncations: &Truncations) -> Self {
+        Self {
+            fastq: self.fastq.truncations(truncations),
+        }
+    }
+}
+
+/// Returns whether a file contains FASTQ records, i.e. starts with `@` rather than `>`.
+pub fn is_fastq<P: AsRef<Path>>(path: P) -> bool {
+    let mut first = [0];
+    open_file(path)
+        .read(&mut first)
+        .expect("Failed to read file")
+        == 1
+        && first[0] == b'@'
+}
+
+/// Record set tagged with its position in the input.
+#[derive(Default)]
+struct NumberedSet<S> {
+    index: usize,
+    set: S,
+}
+
+/// Number of record sets handed out in order, bounding how far ahead of them the sets are read.
+struct ReorderWindow {
+    handed: Mutex<usize>,
+    advanced: Condvar,
+    len: usize,
+    stall_warning: Duration,
+}
+
+impl ReorderWindow {
+    /// Waits until the set `index` is less than `len` sets ahead of the next one handed out,
+    /// warning once if the next set is not handed out within `stall_warning`.
+    /// Returns whether the output stalled.
+    fn wait(&self, index: usize) -> bool {
+        let mut handed = self.handed.lock().unwrap();
+        let mut stalled = false;
+        while index >= handed.saturating_add(self.len) {
+            let waited = *handed;
+            let (guard, timeout) = self
+                .advanced
+                .wait_timeout(handed, self.stall_warning)
+                .unwrap();
+            handed = guard;
+            if timeout.timed_out() && *handed == waited && !stalled {
+                stalled = true;
+                logs::warn(
+                    "stalled_output",
+                    format!(
+                        "the output has been waiting on the record set {waited} for more than {:?}, \
+                         holding back the {} sets read after it",
+                        self.stall_warning, self.len
+                    ),
+                    json!({ "set": waited, "window": self.len }),
+                );
+            }
         }
+        stalled
+    }
+
+    fn advance(&self) {
+        *self.handed.lock().unwrap() += 1;
+        self.advanced.notify_one();
+    }
+
+    /// Lets the reader go on, e.g. if the sets are no longer handed out because of a panic.
+    fn close(&self) {
+        *self.handed.lock().unwrap() = usize::MAX;
+        self.advanced.notify_one();
+    }
+}
+
+/// Closes the window when dropped.
+struct WindowGuard<'a>(&'a ReorderWindow);
+
+impl Drop for WindowGuard<'_> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Reader numbering the record sets it fills, so that they can be put back in order.
+struct NumberedReader<'a, R> {
+    reader: R,
+    next: usize,
+    window: &'a ReorderWindow,
+}
+
+impl<R: RecordSetReader> RecordSetReader for NumberedReader<'_, R> {
+    type RecordSet = NumberedSet<R::RecordSet>;
+    type Err = R::Err;
+
+    fn fill_data(&mut self, rset: &mut Self::RecordSet) -> std::result::Result<bool, Self::Err> {
+        self.window.wait(self.next);
+        rset.index = self.next;
+        self.next += 1;
+        self.reader.fill_data(&mut rset.set)
+    }
+}
+
+/// Processes record sets in parallel like `read_process_recordsets`, but hands them to `func`
+/// in the order of the input, holding back the sets that are completed ahead of their turn.
+/// The sets are read at most `2 * threads` sets ahead of the next one to hand out, and those held
+/// back are swapped with sets already handed out so that their buffers keep being reused.
+/// A warning is printed when a set holds up the full window for more than `stall_warning`.
+fn read_process_ordered<R, O, W, F>(
+    reader: R,
+    threads: u32,
+    queue_len: usize,
+    stall_warning: Duration,
+    work: W,
+    mut func: F,
+) where
+    R: RecordSetReader + Send,
+    R::RecordSet: Default + Send,
+    R::Err: Debug,
+    O: Default + Send,
+    W: Send + Sync + Fn(&mut R::RecordSet, &mut O),
+    F: FnMut(&mut R::RecordSet, &mut O),
+{
+    let window = ReorderWindow {
+        handed: Mutex::new(0),
+        advanced: Condvar::new(),
+        len: 2 * threads.max(1) as usize,
+        stall_warning,
+    };
+    read_process_recordsets(
+        NumberedReader {
+            reader,
+            next: 0,
+            window: &window,
+        },
+        threads,
+        queue_len,
+        |rset, out| work(&mut rset.set, out),
+        |mut rsets| {
+            let _guard = WindowGuard(&window);
+            let mut pending = BTreeMap::new();
+            let mut spares = Vec::new();
+            let mut next = 0;
+            while let Some(result) = rsets.next() {
+                let (rset, out) = result.expect("Error reading record");
+                if rset.index == next {
+                    func(&mut rset.set, out);
+                    window.advance();
+                    next += 1;
+                } else {
+                    let (mut set, mut spare_out) = spares.pop().unwrap_or_default();
+                    swap(&mut rset.set, &mut set);
+                    swap(out, &mut spare_out);
+                    pending.insert(rset.index, (set, spare_out));
+                }
+                while let Some((mut set, mut out)) = pending.remove(&next) {
+                    func(&mut set, &mut out);
+                    window.advance();
+                    spares.push((set, out));
+                    next += 1;
+                }
+            }
+        },
+    );
+}
+
+/// Records which may carry a comment on the separator line (`+`) of FASTQ, such as a copy of the
+/// header, to be written back verbatim.
+pub trait Commented {
+    /// Writes the comment following the `+` of the separator line, if any.
+    fn write_comment<W: Write>(&self, writer: &mut W) -> Result<()>;
+}
+
+impl Commented for fasta::RefRecord<'_> {
+    #[inline]
+    fn write_comment<W: Write>(&self, _: &mut W) -> Result<()> {
+        Ok(())
+    }
+}
+
+thread_local! {
+    static RAW_RECORD: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Commented for fastq::RefRecord<'_> {
+    fn write_comment<W: Write>(&self, writer: &mut W) -> Result<()> {
+        // The parser does not expose the separator line, which is found in the unparsed record.
+        RAW_RECORD.with_borrow_mut(|raw| {
+            raw.clear();
+            self.write_unchanged(&mut *raw)?;
+            let separator = raw
+                .split(|&byte| byte == b'\n')
+                .nth(1 + self.num_seq_lines())
+                .unwrap_or_default();
+            let comment = separator.strip_prefix(b"+").unwrap_or_default();
+            writer.write_all(comment.strip_suffix(b"\r").unwrap_or(comment))
+        })
     }
 }
 
 pub trait ReadProcess: Sized {
-    type Rec<'a>: BaseRecord;
+    type Rec<'a>: BaseRecord + Commented;
+
+    /// Hands the records to `handle_result` in the order of the input when processing them
+    /// in parallel, instead of the order in which the threads complete them.
+    fn ordered(self, ordered: bool) -> Self;
+
+    /// Warns when the ordered output waits longer than `after` on the same record set while the
+    /// sets read after it fill the reorder window, e.g. because of a single slow record.
+    fn stall_warning(self, after: Duration) -> Self;
 
     fn process_rec<F: FnMut(Self::Rec<'_>)>(self, f: F);
 
@@ -83,6 +894,16 @@ pub trait ReadProcess: Sized {
 impl ReadProcess for Fasta {
     type Rec<'a> = fasta::RefRecord<'a>;
 
+    fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    fn stall_warning(mut self, after: Duration) -> Self {
+        self.stall_warning = after;
+        self
+    }
+
     fn process_rec<F: FnMut(Self::Rec<'_>)>(mut self, mut f: F) {
         while let Some(result) = self.reader.next() {
             let record = result.expect("Error reading record");
@@ -101,6 +922,28 @@ impl ReadProcess for Fasta {
         f: F,
         mut handle_result: G,
     ) {
+        if self.ordered {
+            read_process_ordered(
+                self.reader,
+                threads,
+                queue_len,
+                self.stall_warning,
+                |rset: &mut fasta::RecordSet, results: &mut Vec<R>| {
+                    for (i, record) in rset.into_iter().enumerate() {
+                        if i == results.len() {
+                            results.push(R::default());
+                        }
+                        f(record, &mut results[i]);
+                    }
+                },
+                |rset, results| {
+                    for (record, result) in rset.into_iter().zip(results.iter_mut()) {
+                        handle_result(record, result);
+                    }
+                },
+            );
+            return;
+        }
         read_process_fasta_records(
             self.reader,
             threads,
@@ -116,3 +959,538 @@ impl ReadProcess for Fasta {
         .unwrap();
     }
 }
+
+impl ReadProcess for Fastq {
+    type Rec<'a> = fastq::RefRecord<'a>;
+
+    fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    fn stall_warning(mut self, after: Duration) -> Self {
+        self.stall_warning = after;
+        self
+    }
+
+    fn process_rec<F: FnMut(Self::Rec<'_>)>(self, mut f: F) {
+        let mut reader = self.reader;
+        while let Some(input) = reader.current.as_mut() {
+            while let Some(result) = input.reader.next() {
+                match result {
+                    Ok(record) => f(record),
+                    Err(error) => {
+                        skip_truncated(
+                            error,
+                            &input.last_record,
+                            input.name.as_deref(),
+                            &reader.truncations,
+                        )
+                        .expect("Error reading record");
+                        break;
+                    }
+                }
+            }
+            reader.next_input();
+        }
+    }
+
+    fn process_rec_par_result<
+        R: Default + Send,
+        F: Send + Sync + Fn(Self::Rec<'_>, &mut R),
+        G: FnMut(Self::Rec<'_>, &mut R),
+    >(
+        self,
+        threads: u32,
+        queue_len: usize,
+        f: F,
+        mut handle_result: G,
+    ) {
+        if self.ordered {
+            read_process_ordered(
+                self.reader,
+                threads,
+                queue_len,
+                self.stall_warning,
+                |rset: &mut fastq::RecordSet, results: &mut Vec<R>| {
+                    for (i, record) in rset.into_iter().enumerate() {
+                        if i == results.len() {
+                            results.push(R::default());
+                        }
+                        f(record, &mut results[i]);
+                    }
+                },
+                |rset, results| {
+                    for (record, result) in rset.into_iter().zip(results.iter_mut()) {
+                        handle_result(record, result);
+                    }
+                },
+            );
+            return;
+        }
+        read_process_fastq_records(
+            self.reader,
+            threads,
+            queue_len,
+            |record: Self::Rec<'_>, result: &mut R| {
+                f(record, result);
+            },
+            |record, result| {
+                handle_result(record, result);
+                None::<()>
+            },
+        )
+        .unwrap();
+    }
+}
+
+impl ReadProcess for Bam {
+    type Rec<'a> = fastq::RefRecord<'a>;
+
+    fn ordered(self, ordered: bool) -> Self {
+        Self {
+            fastq: self.fastq.ordered(ordered),
+        }
+    }
+
+    fn stall_warning(self, after: Duration) -> Self {
+        Self {
+            fastq: self.fastq.stall_warning(after),
+        }
+    }
+
+    fn process_rec<F: FnMut(Self::Rec<'_>)>(self, f: F) {
+        self.fastq.process_rec(f);
+    }
+
+    fn process_rec_par_result<
+        R: Default + Send,
+        F: Send + Sync + Fn(Self::Rec<'_>, &mut R),
+        G: FnMut(Self::Rec<'_>, &mut R),
+    >(
+        self,
+        threads: u32,
+        queue_len: usize,
+        f: F,
+        handle_result: G,
+    ) {
+        self.fastq
+            .process_rec_par_result(threads, queue_len, f, handle_result);
+    }
+}
+
+/// Random selection of reads by hashing their name (without mate suffix) with a seed,
+/// so that every pass over the reads, as well as both mates, select the same ones.
+struct Sampler {
+    threshold: u64,
+    hash_builder: RandomState,
+    lengths: RangeInclusive<usize>,
+}
+
+impl Sampler {
+    #[inline]
+    fn keep(&self, record: &impl BaseRecord) -> bool {
+        (self.threshold == u64::MAX
+            || self.hash_builder.hash_one(pair_name(record.id_bytes())) < self.threshold)
+            && has_length(record, &self.lengths)
+    }
+}
+
+/// Returns `true` if the number of bases of the record is in the range `lengths`.
+#[inline]
+pub fn has_length(record: &impl BaseRecord, lengths: &RangeInclusive<usize>) -> bool {
+    let (start, end) = (*lengths.start(), *lengths.end());
+    (start == 0 && end == usize::MAX) || lengths.contains(&record.full_seq().len())
+}
+
+/// Reads keeping only a random fraction of the records, the same ones in every pass,
+/// and optionally only those within a range of lengths.
+pub struct Subsample<P> {
+    reads: P,
+    sampler: Sampler,
+}
+
+impl<P: ReadProcess> Subsample<P> {
+    pub fn new(reads: P, fraction: f64, seed: u64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "The subsampled fraction must be between 0 and 1"
+        );
+        Self {
+            reads,
+            sampler: Sampler {
+                threshold: (fraction * u64::MAX as f64) as u64,
+                hash_builder: RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3),
+                lengths: 0..=usize::MAX,
+            },
+        }
+    }
+
+    /// Only keeps the records whose number of bases is in the range `lengths`.
+    pub fn lengths(mut self, lengths: RangeInclusive<usize>) -> Self {
+        self.sampler.lengths = lengths;
+        self
+    }
+}
+
+impl<P: ReadProcess> ReadProcess for Subsample<P> {
+    type Rec<'a> = P::Rec<'a>;
+
+    fn ordered(self, ordered: bool) -> Self {
+        Self {
+            reads: self.reads.ordered(ordered),
+            sampler: self.sampler,
+        }
+    }
+
+    fn stall_warning(self, after: Duration) -> Self {
+        Self {
+            reads: self.reads.stall_warning(after),
+            sampler: self.sampler,
+        }
+    }
+
+    fn process_rec<F: FnMut(Self::Rec<'_>)>(self, mut f: F) {
+        let Self { reads, sampler } = self;
+        reads.process_rec(|record| {
+            if sampler.keep(&record) {
+                f(record)
+            }
+        });
+    }
+
+    fn process_rec_par_result<
+        R: Default + Send,
+        F: Send + Sync + Fn(Self::Rec<'_>, &mut R),
+        G: FnMut(Self::Rec<'_>, &mut R),
+    >(
+        self,
+        threads: u32,
+        queue_len: usize,
+        f: F,
+        mut handle_result: G,
+    ) {
+        let Self { reads, sampler } = self;
+        reads.process_rec_par_result(
+            threads,
+            queue_len,
+            |record, result| {
+                if sampler.keep(&record) {
+                    f(record, result)
+                }
+            },
+            |record, result| {
+                if sampler.keep(&record) {
+                    handle_result(record, result)
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use niffler::Level;
+    use std::fs::remove_file;
+
+    #[test]
+    fn test_subsample() {
+        let content: String = (0..1000)
+            .map(|i| format!(">r{i}/1\nACGT\n>r{i}/2\nTGCA\n"))
+            .collect();
+        let sample = || {
+            let mut ids = Vec::new();
+            let reads = Fasta::from_reader_with_batch_size(Cursor::new(content.clone()), 1 << 10);
+            Subsample::new(reads, 0.3, 42).process_rec_par_result(
+                2,
+                4,
+                |_, _: &mut ()| (),
+                |record, _| ids.push(record.id().unwrap().to_owned()),
+            );
+            ids.sort();
+            ids
+        };
+        let ids = sample();
+        assert_eq!(ids, sample());
+        assert!((500..700).contains(&ids.len()));
+        assert!(ids
+            .chunks(2)
+            .all(|mates| mates[0].trim_end_matches("/1") == mates[1].trim_end_matches("/2")));
+    }
+
+    #[test]
+    fn test_subsample_lengths() {
+        let content: String = (1..=10)
+            .map(|i| format!(">r{i}\n{}\n", "A".repeat(i)))
+            .collect();
+        let mut ids = Vec::new();
+        let reads = Fasta::from_reader_with_batch_size(Cursor::new(content), 1 << 10);
+        Subsample::new(reads, 1.0, 42)
+            .lengths(3..=5)
+            .process_rec(|record| ids.push(record.id().unwrap().to_owned()));
+        assert_eq!(ids, vec!["r3", "r4", "r5"]);
+    }
+
+    #[test]
+    fn test_expand_inputs() {
+        assert!(matches_pattern(b"*.fq.gz", b"reads_1.fq.gz"));
+        assert!(matches_pattern(b"reads_?.fq", b"reads_2.fq"));
+        assert!(!matches_pattern(b"*.fq", b"reads.fq.gz"));
+        let dir = std::env::temp_dir().join(format!("brrr_test_inputs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.fq.gz", "a.fa", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        assert_eq!(
+            expand_inputs(&[dir.to_string_lossy().into_owned()]),
+            vec![path("a.fa"), path("b.fq.gz")]
+        );
+        assert_eq!(expand_inputs(&[path("*.fq*")]), vec![path("b.fq.gz")]);
+        assert_eq!(expand_inputs(&[path("a.fa")]), vec![path("a.fa")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remote() {
+        assert!(is_remote("https://example.org/reads.fq.gz"));
+        assert!(is_remote("s3://bucket/reads.fq"));
+        assert!(!is_remote("reads.fq"));
+        assert_eq!(
+            remote_filename("https://example.org/data/reads.fq.gz?download=1"),
+            "reads.fq.gz"
+        );
+    }
+
+    #[test]
+    fn test_remote_download() {
+        // The download is stubbed with cat, which fails like curl --fail on a missing file.
+        let download = |path: &str| {
+            let mut command = Command::new("cat");
+            command.arg(path).stderr(Stdio::null());
+            Remote::with_command(path, command).unwrap()
+        };
+        let filename =
+            std::env::temp_dir().join(format!("brrr_test_remote_{}.fa", std::process::id()));
+        std::fs::write(&filename, b">r0\nACGT\n").unwrap();
+        let mut content = String::new();
+        download(filename.to_str().unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, ">r0\nACGT\n");
+        remove_file(&filename).unwrap();
+        let mut missing = download("/nonexistent/brrr_reads.fa");
+        assert!(missing.read_to_string(&mut content).is_err());
+    }
+
+    #[test]
+    #[ignore = "requires curl"]
+    fn test_remote_curl() {
+        let filename =
+            std::env::temp_dir().join(format!("brrr_test_remote_curl_{}.fa", std::process::id()));
+        std::fs::write(&filename, b">r0\nACGT\n").unwrap();
+        let mut content = String::new();
+        Remote::open(&format!("file://{}", filename.display()))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, ">r0\nACGT\n");
+        remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_input() {
+        let content = b"@r0\nACGTACGT\n+\nIIIIIIII\n".repeat(1000);
+        for format in [Format::Gzip, Format::Bzip, Format::Xz, Format::Zstd] {
+            let filename = std::env::temp_dir()
+                .join(format!("brrr_test_{}_{format:?}.fq", std::process::id()));
+            let mut writer = niffler::send::to_path(&filename, format, Level::One).unwrap();
+            writer.write_all(&content).unwrap();
+            drop(writer);
+            assert!(is_fastq(&filename));
+            assert_eq!(content_size(&filename), content.len());
+            let mut reads = 0;
+            Fastq::from_file(&filename).process(|nucs| {
+                assert_eq!(nucs.as_slice(), b"ACGTACGT");
+                reads += 1;
+            });
+            assert_eq!(reads, 1000);
+            remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_open_files() {
+        let dir = std::env::temp_dir();
+        let filenames = [
+            dir.join(format!("brrr_test_open_1_{}.fa", std::process::id())),
+            dir.join(format!("brrr_test_open_2_{}.fa", std::process::id())),
+        ];
+        std::fs::write(&filenames[0], b">r0\nACGT").unwrap();
+        std::fs::write(&filenames[1], b">r1\nTTTT\n").unwrap();
+        let mut content = String::new();
+        open_files(&filenames).read_to_string(&mut content).unwrap();
+        assert_eq!(content, ">r0\nACGT\n>r1\nTTTT\n");
+        filenames
+            .iter()
+            .for_each(|filename| remove_file(filename).unwrap());
+    }
+
+    #[test]
+    fn test_ordered() {
+        let content: String = (0..2000).map(|i| format!(">r{i}\nACGT\n")).collect();
+        let mut ids = Vec::new();
+        Fasta::from_reader_with_batch_size(Cursor::new(content), 64)
+            .ordered(true)
+            .process_rec_par_result(
+                4,
+                8,
+                |record, id: &mut Vec<u8>| *id = record.id_bytes().to_vec(),
+                |record, id| {
+                    assert_eq!(record.id_bytes(), id.as_slice());
+                    ids.push(String::from_utf8(id.clone()).unwrap());
+                },
+            );
+        let expected: Vec<_> = (0..2000).map(|i| format!("r{i}")).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn test_ordered_window() {
+        use std::sync::atomic::AtomicUsize;
+        use std::time::Duration;
+        let content: String = (0..200).map(|i| format!(">{i}\nACGT\n")).collect();
+        let started = AtomicUsize::new(0);
+        let mut ahead = None;
+        // Each set holds a single record, the first one being much slower than the others.
+        Fasta::from_reader_with_batch_size(Cursor::new(content), 4)
+            .ordered(true)
+            .process_rec_par_result(
+                2,
+                8,
+                |record, seen: &mut usize| {
+                    let index: usize = record.id().unwrap().parse().unwrap();
+                    if index == 0 {
+                        std::thread::sleep(Duration::from_millis(200));
+                        *seen = started.load(Ordering::Relaxed);
+                    } else {
+                        started.fetch_max(index, Ordering::Relaxed);
+                    }
+                },
+                |record, seen| {
+                    if record.id() == Ok("0") {
+                        ahead = Some(*seen);
+                    }
+                },
+            );
+        // The sets are read at most 2 * threads sets ahead of the one held up.
+        assert!(ahead.unwrap() < 4);
+    }
+
+    #[test]
+    fn test_stalled_window() {
+        let window = ReorderWindow {
+            handed: Mutex::new(0),
+            advanced: Condvar::new(),
+            len: 2,
+            stall_warning: Duration::from_millis(20),
+        };
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                window.advance();
+            });
+            // The set 2 waits for the set 0 to be handed out, which takes longer than the warning.
+            assert!(window.wait(2));
+        });
+        assert!(!window.wait(2));
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let content: String = (0..500)
+            .map(|i| format!("@r{i}\nACGT\n+\nIIII\n"))
+            .collect();
+        let start = content.rfind('@').unwrap() as u64;
+        for cut in [2, 5, 9] {
+            let truncated = &content.as_bytes()[..content.len() - cut];
+            let truncations = Truncations::default();
+            let reads = || {
+                Fastq::from_reader_with_batch_size(Cursor::new(truncated.to_vec()), 64)
+                    .truncations(&truncations)
+            };
+            let mut count = 0;
+            reads().process_rec(|_| count += 1);
+            assert_eq!(count, 499);
+            for ordered in [false, true] {
+                let mut count = 0;
+                reads().ordered(ordered).process_rec_par_result(
+                    2,
+                    4,
+                    |_, _: &mut ()| (),
+                    |_, _| count += 1,
+                );
+                assert_eq!(count, 499);
+            }
+            // The record skipped by every pass is reported once.
+            assert_eq!(truncations.offsets(), vec![start]);
+        }
+        let mut last_record = AtomicU64::new(u64::MAX);
+        let mut tail = TailReader::new(Cursor::new(&content.as_bytes()[..content.len() - 2]));
+        copy(&mut tail, &mut sink()).unwrap();
+        assert_eq!(tail.last_record.load(Ordering::Relaxed), start);
+        // A record with missing qualities in the middle of the input is still invalid.
+        let invalid = content.replacen("IIII", "II", 1);
+        let mut reader = fastq::Reader::new(invalid.as_bytes());
+        let error = reader.next().unwrap().unwrap_err();
+        *last_record.get_mut() = start;
+        let truncations = Truncations::default();
+        assert!(skip_truncated(error, &last_record, None, &truncations).is_err());
+        assert!(truncations.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_inputs() {
+        let dir = std::env::temp_dir();
+        let filenames = [
+            dir.join(format!("brrr_test_truncated_1_{}.fq", std::process::id())),
+            dir.join(format!("brrr_test_truncated_2_{}.fq", std::process::id())),
+        ];
+        let records = |prefix| -> String {
+            (0..100)
+                .map(|i| format!("@{prefix}{i}\nACGT\n+\nIIII\n"))
+                .collect()
+        };
+        let first = records("a");
+        std::fs::write(&filenames[0], &first[..first.len() - 3]).unwrap();
+        std::fs::write(&filenames[1], records("b")).unwrap();
+        // The truncated record ending the first file does not run into the second file.
+        let truncations = Truncations::default();
+        let reads = || Fastq::from_files_with_batch_size(&filenames, 64).truncations(&truncations);
+        let mut ids = Vec::new();
+        reads().process_rec(|record| ids.push(record.id().unwrap().to_owned()));
+        let expected: Vec<_> = (0..99)
+            .map(|i| format!("a{i}"))
+            .chain((0..100).map(|i| format!("b{i}")))
+            .collect();
+        assert_eq!(ids, expected);
+        for ordered in [false, true] {
+            let mut count = 0;
+            reads().ordered(ordered).process_rec_par_result(
+                2,
+                4,
+                |_, _: &mut ()| (),
+                |_, _| count += 1,
+            );
+            assert_eq!(count, 199);
+        }
+        assert_eq!(
+            truncations.offsets(),
+            vec![first.rfind('@').unwrap() as u64]
+        );
+        filenames
+            .iter()
+            .for_each(|filename| remove_file(filename).unwrap());
+    }
+}
@@ -0,0 +1,71 @@
+use crate::reads::{has_length, BaseRecord};
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::ops::RangeInclusive;
+
+/// Selects the reads to correct, the others being passed through or dropped.
+#[derive(Debug)]
+pub struct ReadSelection {
+    include: Option<HashSet<Vec<u8>>>,
+    exclude: Option<HashSet<Vec<u8>>>,
+    lengths: RangeInclusive<usize>,
+}
+
+impl ReadSelection {
+    pub fn new(include_names: Option<&str>, exclude_names: Option<&str>) -> Self {
+        Self {
+            include: include_names.map(read_names),
+            exclude: exclude_names.map(read_names),
+            lengths: 0..=usize::MAX,
+        }
+    }
+
+    /// Only selects the reads whose number of bases is in the range `lengths`.
+    pub fn lengths(mut self, lengths: RangeInclusive<usize>) -> Self {
+        self.lengths = lengths;
+        self
+    }
+
+    pub fn contains<R: BaseRecord>(&self, record: &R) -> bool {
+        let id = record.id_bytes();
+        self.include.as_ref().is_none_or(|names| names.contains(id))
+            && self
+                .exclude
+                .as_ref()
+                .is_none_or(|names| !names.contains(id))
+            && has_length(record, &self.lengths)
+    }
+}
+
+/// Reads a list of read names, one per line.
+/// Only the first word of each line is used, and a leading `>` or `@` is ignored.
+fn read_names(filename: &str) -> HashSet<Vec<u8>> {
+    read_to_string(filename)
+        .expect("Failed to read names file")
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.trim_start_matches(['>', '@']).as_bytes().to_vec())
+        .collect()
+}
+
+/// Mean Phred+33 quality of the bases of a record, or `None` if it has no qualities.
+pub fn mean_quality<R: BaseRecord>(record: &R) -> Option<f64> {
+    let qual = record.opt_full_qual()?;
+    let sum: usize = qual.iter().map(|&q| q.saturating_sub(b'!') as usize).sum();
+    Some(sum as f64 / qual.len().max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seq_io::fastq::Reader;
+
+    #[test]
+    fn test_mean_quality() {
+        let mut reader = Reader::new(&b"@r0\nACGT\n+\n!!?5\n@r1\n\n+\n\n"[..]);
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(mean_quality(&record), Some(12.5));
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(mean_quality(&record), Some(0.0));
+    }
+}
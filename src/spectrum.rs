@@ -0,0 +1,91 @@
+use core::ops::AddAssign;
+use std::io::{Result, Write};
+
+/// Approximate k-mer spectrum, i.e. the number of distinct k-mers per abundance.
+///
+/// Counting filters cannot enumerate their k-mers, so the spectrum is built from
+/// k-mer occurrences instead: a k-mer with abundance `a` occurs `a` times, so each
+/// occurrence contributes `1 / a` to the number of distinct k-mers at abundance `a`.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    distinct: Vec<f64>,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self {
+            distinct: vec![0.0; u8::MAX as usize + 1],
+        }
+    }
+}
+
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Self) {
+        self.distinct
+            .iter_mut()
+            .zip(other.distinct)
+            .for_each(|(x, y)| *x += y);
+    }
+}
+
+impl Spectrum {
+    #[inline]
+    pub fn add(&mut self, abundance: u8) {
+        if abundance > 0 {
+            self.distinct[abundance as usize] += 1.0 / abundance as f64;
+        }
+    }
+
+    pub fn distinct(&self, abundance: u8) -> f64 {
+        self.distinct[abundance as usize]
+    }
+
+    /// Returns the abundance of at least `min_abundance` with the most distinct k-mers,
+    /// i.e. the coverage peak, ignoring the saturated abundance.
+    pub fn peak(&self, min_abundance: u8) -> u8 {
+        (min_abundance.max(1)..u8::MAX)
+            .max_by(|&a, &b| self.distinct(a).total_cmp(&self.distinct(b)))
+            .unwrap_or(min_abundance)
+    }
+
+    /// Writes both spectra side by side as TSV, skipping abundances absent from both.
+    pub fn write_comparison<W: Write>(before: &Self, after: &Self, mut writer: W) -> Result<()> {
+        writeln!(writer, "abundance\tbefore\tafter")?;
+        for abundance in 1..=u8::MAX {
+            let (b, a) = (before.distinct(abundance), after.distinct(abundance));
+            if b > 0.0 || a > 0.0 {
+                writeln!(writer, "{abundance}\t{b:.0}\t{a:.0}")?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spectrum() {
+        let mut spectrum = Spectrum::default();
+        for _ in 0..3 {
+            spectrum.add(3);
+        }
+        spectrum.add(1);
+        spectrum.add(0);
+        assert_eq!(spectrum.distinct(3), 1.0);
+        assert_eq!(spectrum.distinct(1), 1.0);
+        assert_eq!(spectrum.distinct(0), 0.0);
+    }
+
+    #[test]
+    fn test_peak() {
+        let mut spectrum = Spectrum::default();
+        (0..100).for_each(|_| spectrum.add(1));
+        (0..40).for_each(|_| spectrum.add(20));
+        (0..30).for_each(|_| spectrum.add(30));
+        (0..1000).for_each(|_| spectrum.add(u8::MAX));
+        assert_eq!(spectrum.peak(0), 1);
+        assert_eq!(spectrum.peak(2), 20);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::ambiguous::{is_line_break, runs, NucHandling};
+use crate::constants::{K, KT, M};
+use crate::dashbloom::BloomFilter;
+use crate::kmer::{Kmer, RawKmer};
+use crate::reads::ReadProcess;
+use crate::{for_each_kmer_with_minimizer, oriented, W};
+use ahash::RandomState;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, remove_file, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Temporary files holding the super-k-mers of the reads (maximal runs of consecutive k-mers
+/// sharing a minimizer), bucketed by minimizer so that each k-mer lands in a single bucket.
+struct Buckets {
+    filenames: Vec<PathBuf>,
+    writers: Vec<Mutex<BufWriter<File>>>,
+    hash_builder: RandomState,
+}
+
+impl Buckets {
+    fn create(dir: &Path, amount: usize, seed: u64) -> Self {
+        create_dir_all(dir).expect("Failed to create spill directory");
+        let filenames: Vec<_> = (0..amount)
+            .map(|i| dir.join(format!("brrr_{}_{i}.skm", std::process::id())))
+            .collect();
+        let writers = filenames
+            .iter()
+            .map(|filename| {
+                Mutex::new(BufWriter::new(
+                    File::create(filename).expect("Failed to create spill file"),
+                ))
+            })
+            .collect();
+        Self {
+            filenames,
+            writers,
+            hash_builder: RandomState::with_seeds(seed, seed + 1, seed + 2, seed + 3),
+        }
+    }
+
+    /// Writes the super-k-mers of a resolved read to the buckets of their minimizers,
+    /// splitting them at its ambiguous bases.
+    fn add_read(&self, nucs: &[u8], seed: u64, stranded: bool) {
+        for (run, _) in runs(nucs).filter(|(_, ambiguous)| !ambiguous) {
+            let bases: Vec<u8> = nucs[run]
+                .iter()
+                .copied()
+                .filter(|nuc| !is_line_break(nuc))
+                .collect();
+            self.add_run(&bases, seed, stranded);
+        }
+    }
+
+    /// Writes the super-k-mers of a run of ACGT bases to the buckets of their minimizers.
+    fn add_run(&self, bases: &[u8], seed: u64, stranded: bool) {
+        let mut mins = Vec::new();
+        for_each_kmer_with_minimizer(bases.iter(), seed, stranded, |_, min| mins.push(min));
+        let mut start = 0;
+        for run in mins.chunk_by(|a, b| a == b) {
+            let end = start + run.len();
+            let bucket = self.hash_builder.hash_one(run[0]) as usize % self.writers.len();
+            let mut writer = self.writers[bucket].lock().unwrap();
+            writer
+                .write_all(&bases[start..end + K - 1])
+                .and_then(|_| writer.write_all(b"\n"))
+                .expect("Failed to write spill file");
+            start = end;
+        }
+    }
+
+    fn flush(&self) {
+        for writer in self.writers.iter() {
+            writer
+                .lock()
+                .unwrap()
+                .flush()
+                .expect("Failed to flush spill file");
+        }
+    }
+}
+
+/// Counts the k-mers of `reads` exactly in two phases, with a memory footprint bounded by the
+/// largest bucket rather than by the number of distinct k-mers: the super-k-mers of the reads
+/// are first spilled into `buckets` files of `dir`, then the k-mers of each bucket are counted
+/// in memory one bucket at a time, inserting those occurring at least `abundance` times in `solid`.
+/// The letters of the reads other than ACGT are handled according to `handling`, like the Bloom counts.
+/// Returns the number of solid k-mers.
+#[allow(clippy::too_many_arguments)]
+pub fn count_solid_kmers(
+    reads: impl ReadProcess,
+    dir: &Path,
+    buckets: usize,
+    threads: usize,
+    seed: u64,
+    stranded: bool,
+    abundance: u32,
+    solid: &BloomFilter,
+    handling: NucHandling,
+) -> usize {
+    let spill = Buckets::create(dir, buckets, seed + M as u64);
+    reads.process_par(threads as u32, 32, |nucs| {
+        spill.add_read(
+            &handling.resolve(nucs.as_slice()),
+            seed + W as u64,
+            stranded,
+        )
+    });
+    spill.flush();
+    let Buckets {
+        filenames, writers, ..
+    } = spill;
+    drop(writers);
+    let mut solid_kmers = 0;
+    let mut counts: HashMap<RawKmer<K, KT>, u32, RandomState> = HashMap::default();
+    for filename in filenames {
+        let content = read(&filename).expect("Failed to read spill file");
+        remove_file(&filename).expect("Failed to remove spill file");
+        for super_kmer in content.split(|&nuc| nuc == b'\n') {
+            for kmer in RawKmer::<K, KT>::iter_from_nucs(super_kmer.iter()) {
+                *counts.entry(oriented(kmer, stranded)).or_default() += 1;
+            }
+        }
+        for (&kmer, &count) in counts.iter() {
+            if count >= abundance {
+                solid.insert(kmer);
+                solid_kmers += 1;
+            }
+        }
+        counts.clear();
+    }
+    solid_kmers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reads::Fasta;
+    use crate::testing::random_nucs;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_count_solid_kmers() {
+        let nucs = random_nucs(200, 42);
+        let frequent = String::from_utf8(nucs[..100].to_vec()).unwrap();
+        let rare = String::from_utf8(nucs[100..].to_vec()).unwrap();
+        let mut content = String::new();
+        for i in 0..5 {
+            content += &format!(">f{i}\n{frequent}\n");
+        }
+        content += &format!(">r\n{rare}\n");
+        let reads = Fasta::from_reader_with_batch_size(Cursor::new(content), 1 << 10);
+        let solid = BloomFilter::new_with_seed_and_shard_amount(1 << 20, 3, 42, 4);
+        let dir = std::env::temp_dir().join(format!("brrr_test_spill_{}", std::process::id()));
+        let solid_kmers = count_solid_kmers(
+            reads,
+            &dir,
+            4,
+            2,
+            42,
+            false,
+            5,
+            &solid,
+            NucHandling::default(),
+        );
+        let kmers = |nucs: &str| -> Vec<_> {
+            RawKmer::<K, KT>::iter_from_nucs(nucs.as_bytes().iter())
+                .map(|kmer| kmer.canonical())
+                .collect()
+        };
+        assert!(kmers(&frequent).iter().all(|&kmer| solid.contains(kmer)));
+        assert!(!kmers(&rare).iter().any(|&kmer| solid.contains(kmer)));
+        assert!(solid_kmers <= kmers(&frequent).len());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_count_solid_kmers_splits_at_ambiguous() {
+        let mut nucs = random_nucs(100, 42);
+        nucs[50] = b'N';
+        nucs[70..80].make_ascii_lowercase();
+        let read = String::from_utf8(nucs.clone()).unwrap();
+        let content: String = (0..5).map(|i| format!(">f{i}\n{read}\n")).collect();
+        let reads = Fasta::from_reader_with_batch_size(Cursor::new(content), 1 << 10);
+        let solid = BloomFilter::new_with_seed_and_shard_amount(1 << 20, 3, 42, 4);
+        let dir =
+            std::env::temp_dir().join(format!("brrr_test_spill_ambiguous_{}", std::process::id()));
+        let handling = NucHandling {
+            respect_softmask: true,
+            ..NucHandling::default()
+        };
+        count_solid_kmers(reads, &dir, 4, 2, 42, false, 5, &solid, handling);
+        let resolved = handling.resolve(&nucs);
+        let kmers: Vec<_> = crate::correction::run_kmers::<K, KT, RawKmer<K, KT>>(&resolved)
+            .map(|kmer| kmer.canonical())
+            .collect();
+        assert!(!kmers.is_empty());
+        assert!(kmers.iter().all(|&kmer| solid.contains(kmer)));
+        // The k-mer joining the bases around the N never occurs in the reads.
+        let spanning =
+            RawKmer::<K, KT>::iter_from_nucs(resolved.iter().filter(|&&nuc| nuc != b'N'))
+                .nth(50 - K / 2)
+                .unwrap();
+        assert!(!solid.contains(spanning.canonical()));
+    }
+}
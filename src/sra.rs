@@ -0,0 +1,72 @@
+use crate::reads::Remote;
+use std::io::Read;
+
+/// Report of the European Nucleotide Archive listing the FASTQ files of a run,
+/// which mirrors the runs of the SRA.
+const ENA_FILEREPORT: &str =
+    "https://www.ebi.ac.uk/ena/portal/api/filereport?result=read_run&fields=fastq_ftp&accession=";
+
+/// Returns `true` if the input is the accession of a sequencing run (e.g. `SRR1234567`)
+/// from the SRA, ENA or DDBJ.
+pub fn is_accession(input: &str) -> bool {
+    ["SRR", "ERR", "DRR"]
+        .iter()
+        .filter_map(|prefix| input.strip_prefix(prefix))
+        .any(|number| !number.is_empty() && number.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// Extracts the URLs of the FASTQ files from a file report of the ENA.
+fn parse_filereport(report: &str) -> Vec<String> {
+    let mut lines = report.lines();
+    let column = lines
+        .next()
+        .and_then(|header| header.split('\t').position(|field| field == "fastq_ftp"))
+        .expect("Invalid file report");
+    lines
+        .filter_map(|line| line.split('\t').nth(column))
+        .flat_map(|files| files.split(';'))
+        .filter(|file| !file.is_empty())
+        .map(|file| format!("https://{file}"))
+        .collect()
+}
+
+/// Resolves the accession of a run into the URLs of its FASTQ files (one per mate),
+/// which can be streamed as remote inputs.
+pub fn resolve_accession(accession: &str) -> Vec<String> {
+    let mut report = String::new();
+    Remote::open(&format!("{ENA_FILEREPORT}{accession}"))
+        .and_then(|mut remote| remote.read_to_string(&mut report))
+        .unwrap_or_else(|e| panic!("Failed to resolve the accession {accession}: {e}"));
+    let urls = parse_filereport(&report);
+    assert!(!urls.is_empty(), "No FASTQ file found for {accession}");
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_accession() {
+        assert!(is_accession("SRR1234567"));
+        assert!(is_accession("ERR000001"));
+        assert!(!is_accession("SRR"));
+        assert!(!is_accession("SRR12.fq"));
+        assert!(!is_accession("reads.fq"));
+        assert!(!is_accession("naïve.fq"));
+        assert!(!is_accession("SRé"));
+    }
+
+    #[test]
+    fn test_parse_filereport() {
+        let report = "run_accession\tfastq_ftp\n\
+                      SRR1\tftp.sra.ebi.ac.uk/vol1/SRR1_1.fastq.gz;ftp.sra.ebi.ac.uk/vol1/SRR1_2.fastq.gz\n";
+        assert_eq!(
+            parse_filereport(report),
+            vec![
+                "https://ftp.sra.ebi.ac.uk/vol1/SRR1_1.fastq.gz",
+                "https://ftp.sra.ebi.ac.uk/vol1/SRR1_2.fastq.gz"
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::lock::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Statistics that are accumulated separately, e.g. by each thread, then merged.
+pub trait Merge: Default {
+    fn merge(&mut self, other: &Self);
+}
+
+/// Accumulator of the statistics of a thread, with the number of reads
+/// it processed and the time it spent processing them.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadStats<S> {
+    pub stats: S,
+    pub reads: usize,
+    pub busy: Duration,
+}
+
+static NEXT_THREAD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_INDEX: usize = NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Per-thread accumulators of statistics, merged once all the reads are processed.
+///
+/// Each thread is assigned an accumulator on its first use, so that threads
+/// only share an accumulator when there are more threads than accumulators.
+pub struct PerThreadStats<S> {
+    slots: Box<[RwLock<ThreadStats<S>>]>,
+}
+
+impl<S: Merge> PerThreadStats<S> {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            slots: (0..threads.max(1))
+                .map(|_| RwLock::new(ThreadStats::default()))
+                .collect(),
+        }
+    }
+
+    /// Adds the statistics of a read processed by the current thread in `elapsed` time.
+    pub fn add(&self, stats: &S, elapsed: Duration) {
+        let i = THREAD_INDEX.with(|&i| i) % self.slots.len();
+        let mut slot = self.slots[i].write();
+        slot.stats.merge(stats);
+        slot.reads += 1;
+        slot.busy += elapsed;
+    }
+
+    /// Returns the accumulators of the threads that processed reads.
+    pub fn per_thread(self) -> Vec<ThreadStats<S>> {
+        self.slots
+            .into_vec()
+            .into_iter()
+            .map(RwLock::into_inner)
+            .filter(|slot| slot.reads > 0)
+            .collect()
+    }
+}
+
+/// Merges the statistics of all threads.
+pub fn merge_threads<S: Merge>(per_thread: &[ThreadStats<S>]) -> S {
+    let mut stats = S::default();
+    per_thread.iter().for_each(|slot| stats.merge(&slot.stats));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Count(usize);
+
+    impl Merge for Count {
+        fn merge(&mut self, other: &Self) {
+            self.0 += other.0;
+        }
+    }
+
+    #[test]
+    fn test_per_thread_stats() {
+        let stats = PerThreadStats::new(4);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for x in 0..100 {
+                        stats.add(&Count(x), Duration::from_millis(1));
+                    }
+                });
+            }
+        });
+        let per_thread = stats.per_thread();
+        assert_eq!(per_thread.iter().map(|slot| slot.reads).sum::<usize>(), 400);
+        assert_eq!(
+            per_thread.iter().map(|slot| slot.busy).sum::<Duration>(),
+            Duration::from_millis(400)
+        );
+        assert_eq!(merge_threads(&per_thread), Count(4 * 4950));
+    }
+}
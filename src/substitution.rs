@@ -0,0 +1,100 @@
+use std::fs::read_to_string;
+
+/// Probability assumed for the substitutions that are not listed in a profile.
+const DEFAULT_PROBABILITY: f64 = 1e-3;
+
+fn base_index(nuc: u8) -> Option<usize> {
+    b"ACGT".iter().position(|&b| b == nuc)
+}
+
+/// Probabilities of the substitutions made by a sequencer, depending on the preceding base,
+/// used to choose between corrections equally supported by the k-mers.
+#[derive(Debug, Clone)]
+pub struct SubstitutionProfile {
+    /// Log-probability of reading `observed` instead of `actual` after `prev`,
+    /// indexed by `[prev][actual][observed]`.
+    log_probs: [[[f64; 4]; 4]; 4],
+}
+
+impl SubstitutionProfile {
+    /// Reads a profile with one substitution per line: the preceding base (or `*` for any),
+    /// the actual base, the observed base and the probability of the substitution,
+    /// e.g. `G A C 0.01`. Empty lines and lines starting with `#` are ignored.
+    pub fn from_file(filename: &str) -> Self {
+        let content = read_to_string(filename).expect("Failed to read substitution profile");
+        Self::parse(&content).unwrap_or_else(|line| {
+            panic!("Invalid substitution in profile: {line}");
+        })
+    }
+
+    /// Parses a profile, returning the first invalid line on failure.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut log_probs = [[[DEFAULT_PROBABILITY.ln(); 4]; 4]; 4];
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [prev, actual, observed, prob] = fields[..] else {
+                return Err(line.to_owned());
+            };
+            let base = |field: &str| match field.as_bytes() {
+                &[nuc] => base_index(nuc.to_ascii_uppercase()),
+                _ => None,
+            };
+            let prevs = if prev == "*" {
+                0..4
+            } else {
+                let prev = base(prev).ok_or_else(|| line.to_owned())?;
+                prev..(prev + 1)
+            };
+            let (Some(actual), Some(observed), Ok(prob)) =
+                (base(actual), base(observed), prob.parse::<f64>())
+            else {
+                return Err(line.to_owned());
+            };
+            if actual == observed || !(prob > 0.0 && prob <= 1.0) {
+                return Err(line.to_owned());
+            }
+            for prev in prevs {
+                log_probs[prev][actual][observed] = prob.ln();
+            }
+        }
+        Ok(Self { log_probs })
+    }
+
+    /// Returns the log-likelihood of reading `observed` when the sequence is `actual`,
+    /// counting only the substituted positions, `prev` being the base preceding both.
+    pub fn score(&self, prev: u8, actual: &[u8], observed: &[u8]) -> f64 {
+        let mut prev = base_index(prev);
+        let mut score = 0.0;
+        for (&a, &o) in actual.iter().zip(observed) {
+            let (a, o) = (base_index(a), base_index(o));
+            if let (Some(a), Some(o)) = (a, o) {
+                if a != o {
+                    score += match prev {
+                        Some(prev) => self.log_probs[prev][a][o],
+                        None => DEFAULT_PROBABILITY.ln(),
+                    };
+                }
+            }
+            prev = a;
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let profile = SubstitutionProfile::parse("# Illumina\n* A C 0.002\nG A C 0.02\n").unwrap();
+        assert!(profile.score(b'G', b"AT", b"CT") > profile.score(b'T', b"AT", b"CT"));
+        assert!(profile.score(b'T', b"AT", b"CT") > profile.score(b'T', b"AT", b"GT"));
+        assert_eq!(profile.score(b'T', b"AT", b"AT"), 0.0);
+        assert!(SubstitutionProfile::parse("G A A 0.1").is_err());
+        assert!(SubstitutionProfile::parse("G A C").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use crate::bam::complement;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// Random sequence of `len` bases drawn with `seed`.
+pub fn random_nucs(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect()
+}
+
+/// Reverse complement of a sequence of bases.
+pub fn rev_comp(nucs: &[u8]) -> Vec<u8> {
+    nucs.iter().rev().map(|&nuc| complement(nuc)).collect()
+}
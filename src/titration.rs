@@ -0,0 +1,233 @@
+use crate::ambiguous::NucHandling;
+use crate::constants::{K, KT, M};
+use crate::correction::{correct, run_kmers, Stats};
+use crate::dashbloom::CountingBloomFilter;
+use crate::kmer::{Kmer, RawKmer};
+use crate::reads::{
+    content_size, expand_inputs, is_fastq, open_files, Fasta, Fastq, ReadProcess, Subsample,
+    Truncations, DEFAULT_BATCH_SIZE,
+};
+use crate::stats::Merge;
+use crate::{count_kmers, parse_fraction, thread_count};
+use clap::ValueHint;
+
+#[derive(clap::Args, Debug)]
+pub struct TitrateArgs {
+    /// Input files (.fasta, .fa, .fastq, .fq, optionally compressed), whose k-mers are counted together
+    #[arg(required = true, value_hint = ValueHint::FilePath)]
+    input: Vec<String>,
+    /// Number of threads (defaults to all available threads)
+    #[arg(short, long)]
+    threads: Option<usize>,
+    /// Memory (in MB) allocated to Bloom filters (defaults to the decompressed input size)
+    #[arg(short, long)]
+    memory: Option<usize>,
+    /// Abundances above which k-mers would be solid, separated by commas
+    #[arg(short, long, value_delimiter = ',', default_values_t = [2, 3, 4, 5, 6, 8, 10, 15, 20])]
+    abundances: Vec<u8>,
+    /// Fraction of the reads (e.g. 10%) whose correction is simulated for each abundance,
+    /// the k-mers being counted on all of them
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction, default_value_t = 1.0)]
+    sample: f64,
+    /// Number of hashes used in Bloom filters
+    #[arg(short = 'H', long, default_value_t = 3)]
+    hashes: usize,
+    /// Seed used for hash functions
+    #[arg(short, long, default_value_t = 101010)]
+    seed: u64,
+}
+
+/// Reads with weak regions and correctable reads for one abundance threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Titration {
+    pub reads: usize,
+    pub weak_reads: usize,
+    pub correctable_reads: usize,
+}
+
+impl Merge for Titration {
+    fn merge(&mut self, other: &Self) {
+        self.reads += other.reads;
+        self.weak_reads += other.weak_reads;
+        self.correctable_reads += other.correctable_reads;
+    }
+}
+
+/// Adds to `titrations` whether the read `nucs` has weak regions, and whether they can all be
+/// corrected, for each of the `abundances`, given the `abundance` of each k-mer.
+fn titrate_read<F: Fn(RawKmer<K, KT>) -> u8>(
+    nucs: &[u8],
+    abundance: F,
+    abundances: &[u8],
+    titrations: &mut [Titration],
+    buffer: &mut Vec<u8>,
+) {
+    let counts: Vec<u8> = run_kmers(nucs).map(&abundance).collect();
+    let mut stats = Stats::default();
+    for (&threshold, titration) in abundances.iter().zip(titrations.iter_mut()) {
+        titration.reads += 1;
+        if counts.iter().all(|&count| count >= threshold) {
+            continue;
+        }
+        titration.weak_reads += 1;
+        let solid = |kmer| abundance(kmer) >= threshold;
+        correct(
+            nucs.iter(),
+            None,
+            NucHandling::default(),
+            solid,
+            |_| (),
+            |_, _| false,
+            |_| true,
+            None,
+            None,
+            None,
+            buffer,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut stats,
+        );
+        // A read is correctable if none of its k-mers is left weak.
+        if run_kmers(buffer).all(solid) {
+            titration.correctable_reads += 1;
+        }
+    }
+}
+
+/// Counts the k-mers of the inputs once, then reports for each abundance threshold how many reads
+/// would have weak regions and how many of them would be fully corrected.
+pub fn titrate(args: TitrateArgs, truncations: &Truncations) {
+    let inputs = expand_inputs(&args.input);
+    let fastq = is_fastq(&inputs[0]);
+    assert!(
+        inputs.iter().all(|filename| is_fastq(filename) == fastq),
+        "The input files must be either all FASTA or all FASTQ"
+    );
+    if fastq {
+        titrate_with(args, &inputs, |filenames| {
+            Fastq::from_files_with_batch_size(filenames, DEFAULT_BATCH_SIZE)
+                .truncations(truncations)
+        })
+    } else {
+        titrate_with(args, &inputs, |filenames| {
+            Fasta::from_reader_with_batch_size(open_files(filenames), DEFAULT_BATCH_SIZE)
+        })
+    }
+}
+
+fn titrate_with<R: ReadProcess>(
+    args: TitrateArgs,
+    inputs: &[String],
+    open_reads: impl Fn(&[String]) -> R,
+) {
+    let threads = thread_count(args.threads);
+    let size = args.memory.map_or_else(
+        || inputs.iter().map(content_size).sum::<usize>() / 2,
+        |m| m * 1_000_000 / 2,
+    );
+    let new_filter = |seed| {
+        CountingBloomFilter::new_with_seed_and_shard_amount(size, args.hashes, seed, threads * 4)
+    };
+    let min_counts = new_filter(args.seed + M as u64);
+    let kmer_counts = new_filter(args.seed + K as u64);
+    // Counting the k-mers from the first occurrence of their minimizer gives their full abundance,
+    // which every threshold of the ladder can then be applied to.
+    count_kmers(
+        open_reads(inputs),
+        threads,
+        &min_counts,
+        &kmer_counts,
+        1,
+        args.seed,
+        false,
+        NucHandling::default(),
+    );
+    drop(min_counts);
+
+    let abundance = |kmer: RawKmer<K, KT>| kmer_counts.count(kmer.canonical());
+    let mut titrations = vec![Titration::default(); args.abundances.len()];
+    Subsample::new(open_reads(inputs), args.sample, args.seed).process_par_result(
+        threads as u32,
+        32,
+        |nucs, (read_titrations, buffer): &mut (Vec<Titration>, Vec<u8>)| {
+            let nucs: Vec<u8> = nucs.copied().collect();
+            *read_titrations = vec![Titration::default(); args.abundances.len()];
+            titrate_read(&nucs, abundance, &args.abundances, read_titrations, buffer);
+        },
+        |(read_titrations, _)| {
+            for (titration, read_titration) in titrations.iter_mut().zip(read_titrations.iter()) {
+                titration.merge(read_titration);
+            }
+        },
+    );
+
+    println!("abundance\treads\tweak reads\tcorrectable reads");
+    for (threshold, titration) in args.abundances.iter().zip(titrations) {
+        println!(
+            "{threshold}\t{}\t{}\t{}",
+            titration.reads, titration.weak_reads, titration.correctable_reads
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::random_nucs;
+
+    /// Random genome along with the abundance of the k-mers when its k-mers are seen 10 times
+    /// and the others once.
+    fn genome_abundance() -> (Vec<u8>, impl Fn(RawKmer<K, KT>) -> u8) {
+        let genome = random_nucs(200, 42);
+        let kmers: Vec<_> = run_kmers(&genome)
+            .map(|kmer: RawKmer<K, KT>| kmer.canonical())
+            .collect();
+        let abundance = move |kmer: RawKmer<K, KT>| {
+            if kmers.contains(&kmer.canonical()) {
+                10
+            } else {
+                1
+            }
+        };
+        (genome, abundance)
+    }
+
+    #[test]
+    fn test_titrate_read() {
+        let (genome, abundance) = genome_abundance();
+        let mut read = genome[20..170].to_vec();
+        read[75] = if read[75] == b'A' { b'C' } else { b'A' };
+        let abundances = [1, 5, 20];
+        let mut titrations = [Titration::default(); 3];
+        let mut buffer = Vec::new();
+        titrate_read(&read, &abundance, &abundances, &mut titrations, &mut buffer);
+        titrate_read(
+            &genome[..150],
+            &abundance,
+            &abundances,
+            &mut titrations,
+            &mut buffer,
+        );
+        let titration = |weak_reads, correctable_reads| Titration {
+            reads: 2,
+            weak_reads,
+            correctable_reads,
+        };
+        // Every k-mer is solid at 1, the error is corrected at 5, and no k-mer is solid at 20.
+        assert_eq!(
+            titrations,
+            [titration(0, 0), titration(1, 1), titration(2, 0)]
+        );
+    }
+
+    #[test]
+    fn test_titrate_read_skips_ambiguous() {
+        let (genome, abundance) = genome_abundance();
+        // The k-mers joining the bases around the N would be weak.
+        let mut read = genome[20..170].to_vec();
+        read[75] = b'N';
+        let mut titrations = [Titration::default()];
+        titrate_read(&read, abundance, &[5], &mut titrations, &mut Vec::new());
+        assert_eq!(titrations[0].weak_reads, 0);
+    }
+}
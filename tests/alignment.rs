@@ -0,0 +1,44 @@
+//! Runs the binary over the small dataset converted to SAM, whose corrected reads are written
+//! as FASTQ and must be read back as such by the passes over the outputs.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const INPUT: &str = "tests/data/reads.fa";
+
+#[test]
+fn sam_spectrum() {
+    let fasta = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(INPUT)).unwrap();
+    let mut sam = String::from("@HD\tVN:1.6\tSO:unsorted\n");
+    for record in fasta.split('>').skip(1) {
+        let (name, seq) = record.split_once('\n').unwrap();
+        let seq = seq.replace('\n', "");
+        sam.push_str(&format!("{name}\t4\t*\t0\t0\t*\t*\t0\t0\t{seq}\t*\n"));
+    }
+    let dir = std::env::temp_dir().join(format!("brrr_test_sam_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("reads.sam");
+    fs::write(&input, sam).unwrap();
+    let output = dir.join("reads.cor.fq");
+    let spectrum = dir.join("spectrum.tsv");
+    let status = Command::new(env!("CARGO_BIN_EXE_brrr"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--spectrum")
+        .arg(&spectrum)
+        .args(["-m", "1", "-a", "3"])
+        .output()
+        .expect("Failed to run brrr");
+    let result = fs::read_to_string(&spectrum);
+    let reads = fs::read_to_string(&output);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        status.status.success(),
+        "brrr failed: {}",
+        String::from_utf8_lossy(&status.stderr)
+    );
+    assert_eq!(reads.unwrap().lines().count(), 4 * 200);
+    assert!(!result.unwrap().is_empty());
+}
@@ -0,0 +1,66 @@
+//! Runs the binary over a small dataset across a grid of parameters and compares the corrected
+//! reads to the golden files of `tests/golden/`, so that refactors cannot silently change them.
+//! Set `BRRR_UPDATE_GOLDEN=1` to (re)write the golden files after an intended change.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const INPUT: &str = "tests/data/reads.fa";
+const ABUNDANCES: [usize; 2] = [3, 5];
+const HASHES: [usize; 2] = [2, 3];
+const THREADS: [usize; 2] = [1, 4];
+
+/// Directory of the golden files of the values of K and M the binary was built with.
+fn golden_dir() -> PathBuf {
+    let k = option_env!("K").unwrap_or("31");
+    let m = option_env!("M").unwrap_or("21");
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("k{k}_m{m}"))
+}
+
+fn correct(abundance: usize, hashes: usize, threads: usize) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_brrr"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args([INPUT, "-o", "-", "-m", "1", "--ordered"])
+        .args(["-a", &abundance.to_string()])
+        .args(["-H", &hashes.to_string()])
+        .args(["-t", &threads.to_string()])
+        .output()
+        .expect("Failed to run brrr");
+    assert!(
+        output.status.success(),
+        "brrr failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+fn golden_outputs() {
+    let dir = golden_dir();
+    let update = std::env::var_os("BRRR_UPDATE_GOLDEN").is_some();
+    if !update && !dir.exists() {
+        eprintln!("No golden files in {}, skipping", dir.display());
+        return;
+    }
+    fs::create_dir_all(&dir).unwrap();
+    for abundance in ABUNDANCES {
+        for hashes in HASHES {
+            let golden = dir.join(format!("a{abundance}_h{hashes}.fa"));
+            for threads in THREADS {
+                let corrected = correct(abundance, hashes, threads);
+                if update && threads == THREADS[0] {
+                    fs::write(&golden, &corrected).unwrap();
+                }
+                let expected = fs::read(&golden).expect("Missing golden file");
+                assert!(
+                    corrected == expected,
+                    "Output differs from {} with {threads} threads",
+                    golden.display()
+                );
+            }
+        }
+    }
+}